@@ -8,6 +8,9 @@
 #import bevy_pbr::shadows
 #import bevy_pbr::pbr_functions
 
+#import baked_gi::fog
+#import baked_gi::occluders
+
 struct MaterialSetProp {
     scale: f32,
     contrast: f32,
@@ -24,8 +27,10 @@ struct MaterialProperties {
     reflection: MaterialSetProp,
     walls: MaterialSetProp,
     reflection_mask: MaterialSetProp,
-    mist: MaterialSetProp,
     directional_light_blend: f32,
+    dynamic_light_blend: f32,
+    shadow_distance: f32,
+    shadow_fade: f32,
 }
 
 @group(1) @binding(0)
@@ -50,6 +55,108 @@ var reflection_sampler: sampler;
 var walls_texture: texture_2d<f32>;
 @group(1) @binding(10)
 var walls_sampler: sampler;
+@group(1) @binding(11)
+var<uniform> fog_settings: Fog;
+@group(1) @binding(12)
+var occluders_texture: texture_2d<f32>;
+@group(1) @binding(13)
+var shadow_mask_texture: texture_2d<f32>;
+@group(1) @binding(14)
+var shadow_mask_sampler: sampler;
+@group(1) @binding(15)
+var baked_lights_texture: texture_2d<f32>;
+
+//The multiplier a layer contributes before blending, used by the debug views
+fn layer_factor(tex: vec3<f32>, prop: MaterialSetProp) -> vec3<f32> {
+    return pow(tex, vec3<f32>(prop.contrast)) * prop.brightness;
+}
+
+//The occluders are shared by every material, see OccluderData in occluders.rs
+fn load_occluders() -> Occluders {
+    var occluders: Occluders;
+    let settings = textureLoad(occluders_texture, vec2<i32>(0, 0), 0);
+    occluders.count = min(u32(settings.x), MAX_OCCLUDERS);
+    occluders.ambient_strength = settings.y;
+    occluders.shadow_strength = settings.z;
+    occluders.shadow_softness = settings.w;
+    for (var i: u32 = 0u; i < occluders.count; i = i + 1u) {
+        occluders.spheres[i] = textureLoad(occluders_texture, vec2<i32>(i32(i) + 1, 0), 0);
+    }
+    return occluders;
+}
+
+//Lights that are in the lightmap already, see BakedLight in baked_lights.rs
+fn is_baked_light(position: vec3<f32>) -> bool {
+    let count = u32(textureLoad(baked_lights_texture, vec2<i32>(0, 0), 0).x);
+    for (var i: u32 = 0u; i < count; i = i + 1u) {
+        let baked = textureLoad(baked_lights_texture, vec2<i32>(i32(i) + 1, 0), 0).xyz;
+        if (distance(baked, position) < 0.001) {
+            return true;
+        }
+    }
+    return false;
+}
+
+//Accumulates Bevy's clustered point and spot lights for this fragment, except the baked ones
+fn dynamic_lights(
+    world_position: vec4<f32>,
+    world_normal: vec3<f32>,
+    frag_coord: vec4<f32>,
+    N: vec3<f32>,
+    V: vec3<f32>,
+    diffuse_color: vec3<f32>,
+) -> vec3<f32> {
+    let roughness = perceptualRoughnessToRoughness(0.9);
+    let NdotV = max(dot(N, V), 0.0001);
+    let R = reflect(-V, N);
+    let F0 = vec3<f32>(0.04);
+    let is_orthographic = view.projection[3].w == 1.0;
+
+    let view_z = dot(vec4<f32>(
+        view.inverse_view[0].z,
+        view.inverse_view[1].z,
+        view.inverse_view[2].z,
+        view.inverse_view[3].z
+    ), world_position);
+    let cluster_index = fragment_cluster_index(frag_coord.xy, view_z, is_orthographic);
+    let offset_and_counts = unpack_offset_and_counts(cluster_index);
+    let receives_shadows = (mesh.flags & MESH_FLAGS_SHADOW_RECEIVER_BIT) != 0u;
+
+    var light_accum = vec3<f32>(0.0);
+
+    //point lights
+    for (var i: u32 = offset_and_counts[0]; i < offset_and_counts[0] + offset_and_counts[1]; i = i + 1u) {
+        let light_id = get_light_id(i);
+        let light = point_lights.data[light_id];
+        if (is_baked_light(light.position_radius.xyz)) {
+            continue;
+        }
+        var shadow = 1.0;
+        if (receives_shadows && (light.flags & POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_point_shadow(light_id, world_position, world_normal);
+        }
+        let light_contrib = point_light(world_position.xyz, light, roughness, NdotV, N, V, R, F0, diffuse_color);
+        light_accum = light_accum + light_contrib * shadow;
+    }
+
+    //spot lights
+    let spot_start = offset_and_counts[0] + offset_and_counts[1];
+    for (var i: u32 = spot_start; i < spot_start + offset_and_counts[2]; i = i + 1u) {
+        let light_id = get_light_id(i);
+        let light = point_lights.data[light_id];
+        if (is_baked_light(light.position_radius.xyz)) {
+            continue;
+        }
+        var shadow = 1.0;
+        if (receives_shadows && (light.flags & POINT_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_spot_shadow(light_id, world_position, world_normal);
+        }
+        let light_contrib = spot_light(world_position.xyz, light, roughness, NdotV, N, V, R, F0, diffuse_color);
+        light_accum = light_accum + light_contrib * shadow;
+    }
+
+    return light_accum;
+}
 
 struct FragmentInput {
     @builtin(front_facing) is_front: bool,
@@ -71,21 +178,58 @@ fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
     fresnel = clamp(1.0 - fresnel, 0.0, 1.0);
     //------------------------------------------
     var col = vec3<f32>(1.0);
+    let occluders = load_occluders();
+
+    //Sun color is premultiplied by illuminance and exposure on the CPU side
+    var sun_color = vec3<f32>(0.0);
+    var shadow = 1.0;
+    var sphere_shadow = 1.0;
+    var sun_direction = vec3<f32>(0.0, 1.0, 0.0);
+    if (lights.n_directional_lights > 0u) {
+        let sun = lights.directional_lights[0u];
+        sun_color = sun.color.rgb;
+        sun_direction = sun.direction_to_light;
+        if ((sun.flags & DIRECTIONAL_LIGHT_FLAGS_SHADOWS_ENABLED_BIT) != 0u) {
+            shadow = fetch_directional_shadow(0u, in.world_position, in.world_normal);
+        }
+        sphere_shadow = occluder_shadow(occluders, in.world_position.xyz, sun.direction_to_light);
+    }
+
+#ifdef SHADOW_MASK
+    //Real time shadows near the camera, baked ones further out
+    let baked_shadow = textureSample(shadow_mask_texture, shadow_mask_sampler, in.uv * ma.lightmap.scale).r;
+    let view_distance = distance(view.world_position.xyz, in.world_position.xyz);
+    let shadow_fade = clamp((view_distance - ma.shadow_distance) / max(ma.shadow_fade, 0.0001), 0.0, 1.0);
+    shadow = mix(shadow, baked_shadow, shadow_fade) * sphere_shadow;
+    //Dynamic objects can't be in the lightmap, the sun is already shadowed by them above
+    let occlusion = occluder_ambient(occluders, in.world_position.xyz, N);
+#else
+    //The lightmap has the sun baked in, so it isn't added again.
+    //Dynamic objects can't be in the lightmap, so darken it around and behind them
+    let occlusion = occluder_ambient(occluders, in.world_position.xyz, N)
+        * mix(1.0, sphere_shadow, occluders.shadow_strength);
+    shadow = shadow * sphere_shadow;
+#endif
 
     let lightmap = textureSample(lightmap_texture, lightmap_sampler, in.uv * ma.lightmap.scale).rgb;
     col = mix(col, col * pow(lightmap, vec3<f32>(ma.lightmap.contrast)) * ma.lightmap.brightness, ma.lightmap.blend);
+    col = col * occlusion;
+#ifdef SHADOW_MASK
+    //The lightmap only has indirect light, direct sun is added before the albedo layers
+    col = col + sun_color * max(dot(N, sun_direction), 0.0) * shadow * ma.directional_light_blend;
+#endif
 
     let base_tex_a = textureSample(base_texture, base_sampler, in.uv * ma.base_a.scale).rgb;
-    col = mix(col, col * pow(base_tex_a, vec3<f32>(ma.base_a.contrast)) * ma.base_a.brightness, ma.base_a.blend);
+    col = mix(col, col * layer_factor(base_tex_a, ma.base_a), ma.base_a.blend);
 
     let base_tex_b = textureSample(base_texture, base_sampler, in.uv * ma.base_b.scale).rgb;
-    col = mix(col, col * pow(base_tex_b, vec3<f32>(ma.base_b.contrast)) * ma.base_b.brightness, ma.base_b.blend);
+    col = mix(col, col * layer_factor(base_tex_b, ma.base_b), ma.base_b.blend);
 
     let var_tex_a = textureSample(vary_texture, vary_sampler, in.uv * ma.vary_a.scale).rgb;
-    col = mix(col, col * pow(var_tex_a, vec3<f32>(ma.vary_a.contrast)) * ma.vary_a.brightness, ma.vary_a.blend);
+    col = mix(col, col * layer_factor(var_tex_a, ma.vary_a), ma.vary_a.blend);
 
     let var_tex_b = textureSample(vary_texture, vary_sampler, in.uv * ma.vary_b.scale).rgb;
-    col = mix(col, col * pow(var_tex_b, vec3<f32>(ma.vary_b.contrast)) * ma.vary_b.brightness, ma.vary_b.blend);
+    col = mix(col, col * layer_factor(var_tex_b, ma.vary_b), ma.vary_b.blend);
     //Use variation textures to create ripples in the water UVs
     var ref_uv = normalize(reflect(V, N)).xy * ma.reflection.scale + var_tex_a.y * 0.01 + var_tex_b.y * 0.01; 
 
@@ -103,14 +247,74 @@ fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
 
     col = mix(col, refl, clamp(ceil((in.world_normal.y - 0.99))*100.0,0.0,1.0));
 
-    var mist = pow(clamp(ma.mist.scale-in.frag_coord.w,0.0,1.0), ma.mist.contrast) * ma.mist.brightness;
-
+    //The albedo layers without any light. Each layer above is mix(col, col * layer, blend),
+    //which is col * mix(1.0, layer, blend).
+    let albedo = mix(vec3<f32>(1.0), layer_factor(base_tex_a, ma.base_a), ma.base_a.blend)
+        * mix(vec3<f32>(1.0), layer_factor(base_tex_b, ma.base_b), ma.base_b.blend)
+        * mix(vec3<f32>(1.0), layer_factor(var_tex_a, ma.vary_a), ma.vary_a.blend)
+        * mix(vec3<f32>(1.0), layer_factor(var_tex_b, ma.vary_b), ma.vary_b.blend)
+        * mix(vec3<f32>(1.0), walls_tex, walls_mask * ma.walls.blend);
 
-    //var shadow = fetch_point_shadow(get_light_id(0u), in.world_position, in.world_normal);
-    var shadow = fetch_directional_shadow(0u, in.world_position, in.world_normal);
+    //Lights that aren't in the lightmap (flashlights, moving lights, etc...)
+    let dynamic_light = dynamic_lights(in.world_position, in.world_normal, in.frag_coord, N, V, albedo);
+    col = col + dynamic_light * ma.dynamic_light_blend;
     
-    col = col + col * shadow * vec3<f32>(1.0,0.9,0.5) * 4.0 * ma.directional_light_blend;
-    col = mix(col, col + mist, ma.mist.blend);
-    return tone_mapping(vec4<f32>(col, 1.0));
-    //return vec4<f32>(vec3<f32>(fresnel), 1.0);
+    col = apply_fog(fog_settings, col, in.world_position.xyz, view.world_position.xyz);
+
+    //Debug views output a single term, see DebugView in custom_material.rs
+#ifdef DEBUG_LIGHTMAP
+    return vec4<f32>(lightmap, 1.0);
+#endif
+#ifdef DEBUG_BASE_A
+    return vec4<f32>(layer_factor(base_tex_a, ma.base_a), 1.0);
+#endif
+#ifdef DEBUG_BASE_B
+    return vec4<f32>(layer_factor(base_tex_b, ma.base_b), 1.0);
+#endif
+#ifdef DEBUG_VARY_A
+    return vec4<f32>(layer_factor(var_tex_a, ma.vary_a), 1.0);
+#endif
+#ifdef DEBUG_VARY_B
+    return vec4<f32>(layer_factor(var_tex_b, ma.vary_b), 1.0);
+#endif
+#ifdef DEBUG_REFLECTION
+    return vec4<f32>(layer_factor(ref_sample, ma.reflection), 1.0);
+#endif
+#ifdef DEBUG_WALLS
+    return vec4<f32>(walls_tex, 1.0);
+#endif
+#ifdef DEBUG_PUDDLE_MASK
+    return vec4<f32>(vec3<f32>(puddle_mask), 1.0);
+#endif
+#ifdef DEBUG_WALLS_MASK
+    return vec4<f32>(vec3<f32>(walls_mask), 1.0);
+#endif
+#ifdef DEBUG_FRESNEL
+    return vec4<f32>(vec3<f32>(fresnel), 1.0);
+#endif
+#ifdef DEBUG_SHADOW
+    return vec4<f32>(vec3<f32>(shadow), 1.0);
+#endif
+#ifdef DEBUG_FOG
+    return vec4<f32>(vec3<f32>(fog_opacity(fog_settings, in.world_position.xyz, view.world_position.xyz)), 1.0);
+#endif
+#ifdef DEBUG_OCCLUSION
+    return vec4<f32>(vec3<f32>(occlusion), 1.0);
+#endif
+#ifdef DEBUG_UV_CHECKER
+    let checker_cell = floor(in.uv * 16.0);
+    let checker = (checker_cell.x + checker_cell.y) % 2.0;
+    return vec4<f32>(mix(vec3<f32>(0.1), vec3<f32>(0.9), abs(checker)) * vec3<f32>(fract(in.uv), 1.0), 1.0);
+#endif
+#ifdef DEBUG_TEXEL_DENSITY
+    //Lightmap texels per screen pixel. red: blurry/magnified, green: ~1, blue: wasted resolution
+    let lightmap_texels = in.uv * ma.lightmap.scale * vec2<f32>(textureDimensions(lightmap_texture));
+    let texels_per_pixel = length(fwidth(lightmap_texels));
+    let density_color = hsv2rgb(clamp(0.33 + log2(texels_per_pixel) * 0.15, 0.0, 0.66), 1.0, 1.0);
+    let texel_cell = floor(lightmap_texels);
+    let texel_checker = abs((texel_cell.x + texel_cell.y) % 2.0);
+    return vec4<f32>(density_color * (0.75 + 0.25 * texel_checker), 1.0);
+#endif
+
+    return vec4<f32>(col, 1.0);
 }
@@ -1,6 +1,8 @@
 #import bevy_pbr::mesh_view_bindings
 #import bevy_pbr::mesh_bindings
 
+#import baked_gi::fog
+
 struct EmissiveMaterial {
     emissive: vec4<f32>,
 }
@@ -11,6 +13,8 @@ var<uniform> material: EmissiveMaterial;
 var emissive_texture: texture_2d<f32>;
 @group(1) @binding(2)
 var emissive_sampler: sampler;
+@group(1) @binding(3)
+var<uniform> fog_settings: Fog;
 
 struct FragmentInput {
     @builtin(front_facing) is_front: bool,
@@ -20,5 +24,7 @@ struct FragmentInput {
 
 @fragment
 fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
-    return vec4<f32>(pow(textureSample(emissive_texture, emissive_sampler, in.uv).rgb, vec3<f32>(1.0)), 1.0);
+    var col = pow(textureSample(emissive_texture, emissive_sampler, in.uv).rgb, vec3<f32>(1.0));
+    col = apply_fog(fog_settings, col, in.world_position.xyz, view.world_position.xyz);
+    return vec4<f32>(col, 1.0);
 }
@@ -0,0 +1,13 @@
+#import bevy_core_pipeline::fullscreen_vertex_shader
+#import baked_gi::tonemapping
+
+@group(0) @binding(0)
+var view_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<uniform> view_exposure: Exposure;
+
+@fragment
+fn fragment(in: FullscreenVertexOutput) -> @location(0) vec4<f32> {
+    let color = textureLoad(view_texture, vec2<i32>(in.position.xy), 0);
+    return vec4<f32>(apply_exposure(view_exposure, color.rgb), color.a);
+}
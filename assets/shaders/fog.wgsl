@@ -0,0 +1,30 @@
+#define_import_path baked_gi::fog
+
+struct Fog {
+    color: vec4<f32>,
+    density: f32,
+    height_falloff: f32,
+    base_height: f32,
+    max_opacity: f32,
+}
+
+//Exponential height fog integrated along the view ray
+//See https://iquilezles.org/articles/fog/
+fn fog_opacity(fog: Fog, world_position: vec3<f32>, view_position: vec3<f32>) -> f32 {
+    let ray = world_position - view_position;
+    let distance = length(ray);
+    let ray_y = ray.y / max(distance, 0.0001);
+
+    //density at the camera height
+    var amount = fog.density * exp(-fog.height_falloff * (view_position.y - fog.base_height)) * distance;
+    let falloff = fog.height_falloff * ray_y * distance;
+    if (abs(falloff) > 0.0001) {
+        amount = amount * (1.0 - exp(-falloff)) / falloff;
+    }
+
+    return min(1.0 - exp(-amount), fog.max_opacity);
+}
+
+fn apply_fog(fog: Fog, color: vec3<f32>, world_position: vec3<f32>, view_position: vec3<f32>) -> vec3<f32> {
+    return mix(color, fog.color.rgb, fog_opacity(fog, world_position, view_position));
+}
@@ -0,0 +1,49 @@
+#import bevy_pbr::mesh_view_bindings
+#import bevy_pbr::mesh_bindings
+
+#import bevy_pbr::pbr_types
+#import bevy_pbr::utils
+#import bevy_pbr::clustered_forward
+#import bevy_pbr::lighting
+#import bevy_pbr::shadows
+#import bevy_pbr::pbr_functions
+
+#import baked_gi::fog
+
+struct FogStandardMaterial {
+    base_color: vec4<f32>,
+    perceptual_roughness: f32,
+    metallic: f32,
+    reflectance: f32,
+}
+
+@group(1) @binding(0)
+var<uniform> material: FogStandardMaterial;
+@group(1) @binding(1)
+var<uniform> fog_settings: Fog;
+
+struct FragmentInput {
+    @builtin(front_facing) is_front: bool,
+    @builtin(position) frag_coord: vec4<f32>,
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+    var pbr_input = pbr_input_new();
+    pbr_input.material.base_color = material.base_color;
+    pbr_input.material.perceptual_roughness = material.perceptual_roughness;
+    pbr_input.material.metallic = material.metallic;
+    pbr_input.material.reflectance = material.reflectance;
+
+    pbr_input.frag_coord = in.frag_coord;
+    pbr_input.world_position = in.world_position;
+    pbr_input.world_normal = prepare_world_normal(in.world_normal, false, in.is_front);
+    pbr_input.is_orthographic = view.projection[3].w == 1.0;
+    pbr_input.N = pbr_input.world_normal;
+    pbr_input.V = calculate_view(in.world_position, pbr_input.is_orthographic);
+
+    var output_color = pbr(pbr_input);
+    let col = apply_fog(fog_settings, output_color.rgb, in.world_position.xyz, view.world_position.xyz);
+    return vec4<f32>(col, output_color.a);
+}
@@ -0,0 +1,19 @@
+struct LineMaterial {
+    color: vec4<f32>,
+}
+
+@group(1) @binding(0)
+var<uniform> material: LineMaterial;
+
+struct FragmentInput {
+    #import bevy_pbr::mesh_vertex_output
+};
+
+@fragment
+fn fragment(in: FragmentInput) -> @location(0) vec4<f32> {
+#ifdef VERTEX_COLORS
+    return material.color * in.color;
+#else
+    return material.color;
+#endif
+}
@@ -0,0 +1,45 @@
+// Log2 luminance histogram of the linear view color, read back for auto exposure in src/auto_exposure.rs
+
+// Keep in sync with src/auto_exposure.rs
+let HISTOGRAM_BINS: u32 = 64u;
+let MIN_LOG2_LUMINANCE: f32 = -10.0;
+let MAX_LOG2_LUMINANCE: f32 = 6.0;
+
+@group(0) @binding(0)
+var view_texture: texture_2d<f32>;
+@group(0) @binding(1)
+var<storage, read_write> histogram: array<atomic<u32>, 64>;
+
+var<workgroup> local_histogram: array<atomic<u32>, 64>;
+
+fn luminance_bin(color: vec3<f32>) -> u32 {
+    let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+    if (luminance < exp2(MIN_LOG2_LUMINANCE)) {
+        return 0u;
+    }
+    let t = (log2(luminance) - MIN_LOG2_LUMINANCE) / (MAX_LOG2_LUMINANCE - MIN_LOG2_LUMINANCE);
+    return min(u32(saturate(t) * f32(HISTOGRAM_BINS)), HISTOGRAM_BINS - 1u);
+}
+
+@compute @workgroup_size(16, 16, 1)
+fn build_histogram(
+    @builtin(global_invocation_id) id: vec3<u32>,
+    @builtin(local_invocation_index) index: u32,
+) {
+    if (index < HISTOGRAM_BINS) {
+        atomicStore(&local_histogram[index], 0u);
+    }
+    workgroupBarrier();
+
+    let size = vec2<u32>(textureDimensions(view_texture));
+    if (id.x < size.x && id.y < size.y) {
+        let color = textureLoad(view_texture, vec2<i32>(id.xy), 0).rgb;
+        atomicAdd(&local_histogram[luminance_bin(color)], 1u);
+    }
+    workgroupBarrier();
+
+    //One global atomic per bin and workgroup instead of per pixel
+    if (index < HISTOGRAM_BINS) {
+        atomicAdd(&histogram[index], atomicLoad(&local_histogram[index]));
+    }
+}
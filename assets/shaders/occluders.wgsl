@@ -0,0 +1,55 @@
+#define_import_path baked_gi::occluders
+
+let MAX_OCCLUDERS: u32 = 32u;
+
+//Read from the shared occluder texture, see load_occluders in custom_material.wgsl
+struct Occluders {
+    //xyz is the center, w the radius
+    spheres: array<vec4<f32>, 32>,
+    count: u32,
+    ambient_strength: f32,
+    shadow_strength: f32,
+    shadow_softness: f32,
+}
+
+//Fraction of the hemisphere above the normal that's covered by the sphere, cosine weighted
+//See https://iquilezles.org/articles/sphereao/
+fn sphere_occlusion(position: vec3<f32>, normal: vec3<f32>, sphere: vec4<f32>) -> f32 {
+    let offset = sphere.xyz - position;
+    let distance = length(offset);
+    let NdotL = dot(normal, offset / distance);
+    return clamp(NdotL * (sphere.w * sphere.w) / (distance * distance), 0.0, 1.0);
+}
+
+//Penumbra of the sphere along a ray, 1.0 is unshadowed
+//See https://iquilezles.org/articles/sphereshadow/
+fn sphere_soft_shadow(position: vec3<f32>, direction: vec3<f32>, sphere: vec4<f32>, softness: f32) -> f32 {
+    let offset = position - sphere.xyz;
+    let b = dot(offset, direction);
+    let c = dot(offset, offset) - sphere.w * sphere.w;
+    let h = b * b - c;
+    let d = sqrt(max(0.0, sphere.w * sphere.w - h)) - sphere.w;
+    let t = -b - sqrt(max(h, 0.0));
+    if (t < 0.0) {
+        return 1.0;
+    }
+    return smoothstep(0.0, 1.0, 2.5 * softness * d / t);
+}
+
+//Darkens ambient (baked) light near the occluders
+fn occluder_ambient(occluders: Occluders, position: vec3<f32>, normal: vec3<f32>) -> f32 {
+    var occlusion = 1.0;
+    for (var i: u32 = 0u; i < min(occluders.count, MAX_OCCLUDERS); i = i + 1u) {
+        occlusion = occlusion * (1.0 - sphere_occlusion(position, normal, occluders.spheres[i]));
+    }
+    return mix(1.0, occlusion, occluders.ambient_strength);
+}
+
+//Shadow cast by the occluders along direction_to_light
+fn occluder_shadow(occluders: Occluders, position: vec3<f32>, direction_to_light: vec3<f32>) -> f32 {
+    var shadow = 1.0;
+    for (var i: u32 = 0u; i < min(occluders.count, MAX_OCCLUDERS); i = i + 1u) {
+        shadow = min(shadow, sphere_soft_shadow(position, direction_to_light, occluders.spheres[i], occluders.shadow_softness));
+    }
+    return shadow;
+}
@@ -0,0 +1,81 @@
+#define_import_path baked_gi::tonemapping
+
+struct Exposure {
+    exposure: f32,
+    tonemapper: u32,
+}
+
+let TONEMAPPER_NONE: u32 = 0u;
+let TONEMAPPER_REINHARD: u32 = 1u;
+let TONEMAPPER_ACES: u32 = 2u;
+let TONEMAPPER_AGX: u32 = 3u;
+
+fn tonemap_luminance(v: vec3<f32>) -> f32 {
+    return dot(v, vec3<f32>(0.2126, 0.7152, 0.0722));
+}
+
+//Same as bevy's reinhard_luminance
+fn tonemap_reinhard(color: vec3<f32>) -> vec3<f32> {
+    let l_old = tonemap_luminance(color);
+    let l_new = l_old / (1.0 + l_old);
+    return color * (l_new / max(l_old, 0.00001));
+}
+
+//Krzysztof Narkowicz, "ACES Filmic Tone Mapping Curve"
+fn tonemap_aces(color: vec3<f32>) -> vec3<f32> {
+    let a = 2.51;
+    let b = 0.03;
+    let c = 2.43;
+    let d = 0.59;
+    let e = 0.14;
+    return saturate((color * (a * color + b)) / (color * (c * color + d) + e));
+}
+
+//Minimal AgX by Benjamin Wrensch, 6th order polynomial contrast approximation
+//https://iolite-engine.com/blog_posts/minimal_agx_implementation
+fn agx_contrast_approx(x: vec3<f32>) -> vec3<f32> {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2
+        - 40.14 * x4 * x
+        + 31.96 * x4
+        - 6.868 * x2 * x
+        + 0.4298 * x2
+        + 0.1191 * x
+        - 0.00232;
+}
+
+fn tonemap_agx(color: vec3<f32>) -> vec3<f32> {
+    let agx_mat = mat3x3<f32>(
+        vec3<f32>(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3<f32>(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3<f32>(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let agx_mat_inv = mat3x3<f32>(
+        vec3<f32>(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3<f32>(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3<f32>(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var c = agx_mat * color;
+    c = clamp(log2(max(c, vec3<f32>(1e-10))), vec3<f32>(min_ev), vec3<f32>(max_ev));
+    c = (c - min_ev) / (max_ev - min_ev);
+    c = agx_contrast_approx(c);
+    c = agx_mat_inv * c;
+    //AgX outputs display encoded values, but the render target is sRGB
+    return pow(max(c, vec3<f32>(0.0)), vec3<f32>(2.2));
+}
+
+fn apply_exposure(e: Exposure, color: vec3<f32>) -> vec3<f32> {
+    let exposed = color * e.exposure;
+    if (e.tonemapper == TONEMAPPER_REINHARD) {
+        return tonemap_reinhard(exposed);
+    } else if (e.tonemapper == TONEMAPPER_ACES) {
+        return tonemap_aces(exposed);
+    } else if (e.tonemapper == TONEMAPPER_AGX) {
+        return tonemap_agx(exposed);
+    }
+    return exposed;
+}
@@ -0,0 +1,135 @@
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    window::{PresentMode, WindowId, WindowResized},
+};
+use serde::{Deserialize, Serialize};
+
+#[cfg(feature = "planets")]
+use crate::planets::PlanetSpawnConfig;
+use crate::ron_file::{load_ron, save_ron};
+use crate::walk_camera::WalkSettings;
+use crate::CurrentLevel;
+
+pub const SETTINGS_FILE: &str = "settings.ron";
+
+// Fly camera speeds, see fly_camera
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ControllerSpeeds {
+    pub walk_speed: f32,
+    pub run_speed: f32,
+    pub sensitivity: f32,
+}
+
+// Saved to SETTINGS_FILE on exit and loaded on start, missing fields keep their defaults
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct AppSettings {
+    // Level loaded on start
+    pub level: Option<String>,
+    pub window_width: f32,
+    pub window_height: f32,
+    pub vsync: bool,
+    pub planet_count: u32,
+    // Fly camera speeds, None keeps the controller's defaults
+    pub controller: Option<ControllerSpeeds>,
+    pub walk: WalkSettings,
+    // The Settings window can be closed, F1 opens it again
+    pub show_settings: bool,
+}
+
+impl Default for AppSettings {
+    fn default() -> Self {
+        #[cfg(feature = "planets")]
+        let planet_count = PlanetSpawnConfig::default().count;
+        // Builds with and without planets share the settings file
+        #[cfg(not(feature = "planets"))]
+        let planet_count = 30;
+        AppSettings {
+            level: None,
+            window_width: 1280.0,
+            window_height: 720.0,
+            vsync: true,
+            planet_count,
+            controller: None,
+            walk: WalkSettings::default(),
+            show_settings: true,
+        }
+    }
+}
+
+impl AppSettings {
+    // Defaults if there's no settings file yet
+    pub fn load() -> Self {
+        if !std::path::Path::new(SETTINGS_FILE).exists() {
+            return AppSettings::default();
+        }
+        load_ron(SETTINGS_FILE).unwrap_or_else(|e| {
+            eprintln!("Failed to load {}, using defaults: {}", SETTINGS_FILE, e);
+            AppSettings::default()
+        })
+    }
+
+    pub fn current_level(&self) -> CurrentLevel {
+        self.level
+            .as_deref()
+            .and_then(CurrentLevel::from_name)
+            .unwrap_or_default()
+    }
+
+    pub fn window(&self) -> WindowDescriptor {
+        WindowDescriptor {
+            width: self.window_width,
+            height: self.window_height,
+            present_mode: if self.vsync {
+                PresentMode::AutoVsync
+            } else {
+                PresentMode::AutoNoVsync
+            },
+            ..default()
+        }
+    }
+
+    #[cfg(feature = "planets")]
+    pub fn spawn_config(&self) -> PlanetSpawnConfig {
+        PlanetSpawnConfig {
+            count: self.planet_count,
+            ..default()
+        }
+    }
+}
+
+// The window is already gone when the app exits, so its size is kept up to date here
+pub fn track_window_size(
+    mut resized: EventReader<WindowResized>,
+    mut settings: ResMut<AppSettings>,
+) {
+    for event in resized.iter() {
+        if event.id == WindowId::primary() {
+            settings.window_width = event.width;
+            settings.window_height = event.height;
+        }
+    }
+}
+
+pub fn save_settings_on_exit(
+    mut exits: EventReader<AppExit>,
+    mut settings: ResMut<AppSettings>,
+    level: Res<CurrentLevel>,
+    walk: Res<WalkSettings>,
+    #[cfg(feature = "planets")] spawn_config: Res<PlanetSpawnConfig>,
+) {
+    if exits.iter().last().is_none() {
+        return;
+    }
+    settings.level = level.name().map(String::from);
+    settings.walk = walk.clone();
+    #[cfg(feature = "planets")]
+    {
+        settings.planet_count = spawn_config.count;
+    }
+    match save_ron(SETTINGS_FILE, &*settings) {
+        Ok(()) => info!("Saved settings to {}", SETTINGS_FILE),
+        Err(e) => error!("Failed to save settings to {}: {}", SETTINGS_FILE, e),
+    }
+}
@@ -0,0 +1,419 @@
+use bevy::{
+    app::AppExit,
+    asset::{HandleId, LoadState},
+    ecs::schedule::ShouldRun,
+    prelude::*,
+    utils::HashSet,
+};
+use bevy_egui::{egui, EguiContext};
+
+use crate::custom_material::CustomMaterial;
+use crate::emissive_material::EmissiveMaterial;
+use crate::fog::FogSettings;
+use crate::{CurrentLevel, LevelItem};
+
+// Seconds a full fade out or in takes
+const FADE_TIME: f32 = 0.4;
+// How dark the screen gets while paused
+const PAUSED_FADE: f32 = 0.5;
+const PAUSE_KEY: KeyCode = KeyCode::P;
+const RELOAD_KEY: KeyCode = KeyCode::F5;
+const NEXT_LEVEL_KEY: KeyCode = KeyCode::F6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum AppState {
+    // No level loaded
+    Menu,
+    // Waiting for the level's meshes and textures behind the loading screen
+    Loading,
+    InLevel,
+    // Pushed on top of InLevel, time is stopped
+    Paused,
+}
+
+impl AppState {
+    // The state to be in once the level has been spawned
+    pub fn for_level(level: CurrentLevel) -> Self {
+        match level {
+            CurrentLevel::None => AppState::Menu,
+            _ => AppState::Loading,
+        }
+    }
+}
+
+// Fades out, swaps the level and shows the loading screen. CurrentLevel::None goes back to the menu
+pub struct LoadLevel(pub CurrentLevel);
+
+// Sent once every mesh and texture of the level has loaded
+pub struct LevelLoaded(pub CurrentLevel);
+
+// Sent when the level's entities are despawned, before the next level is spawned
+pub struct LevelUnloaded(pub CurrentLevel);
+
+// A level change waits for the screen to fade out, so the switch isn't seen
+#[derive(Resource, Debug)]
+pub struct LevelTransition {
+    next: Option<CurrentLevel>,
+    // 0.0 is clear, 1.0 is black
+    fade: f32,
+}
+
+impl Default for LevelTransition {
+    fn default() -> Self {
+        // Start black and fade in
+        LevelTransition {
+            next: None,
+            fade: 1.0,
+        }
+    }
+}
+
+impl LevelTransition {
+    // A level is waiting for the fade out
+    pub fn pending(&self) -> bool {
+        self.next.is_some()
+    }
+}
+
+// The last request wins if several come in while fading out
+pub fn load_level_events(
+    mut events: EventReader<LoadLevel>,
+    mut transition: ResMut<LevelTransition>,
+) {
+    if let Some(LoadLevel(level)) = events.iter().last() {
+        transition.next = Some(*level);
+    }
+}
+
+#[derive(Resource, Debug, Default, Clone, Copy)]
+pub struct LoadingProgress {
+    pub loaded: usize,
+    pub total: usize,
+}
+
+impl LoadingProgress {
+    pub fn fraction(&self) -> f32 {
+        if self.total == 0 {
+            0.0
+        } else {
+            self.loaded as f32 / self.total as f32
+        }
+    }
+}
+
+#[derive(Component)]
+struct FadeOverlay;
+
+#[derive(Component)]
+struct LoadingBar;
+
+#[derive(Component)]
+struct LoadingBarFill;
+
+fn spawn_overlay(mut com: Commands) {
+    com.spawn(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+            justify_content: JustifyContent::Center,
+            align_items: AlignItems::Center,
+            ..default()
+        },
+        background_color: Color::BLACK.into(),
+        // Above any other UI
+        z_index: ZIndex::Global(i32::MAX),
+        ..default()
+    })
+    .insert(FadeOverlay)
+    .with_children(|parent| {
+        parent
+            .spawn(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Percent(40.0), Val::Px(12.0)),
+                    ..default()
+                },
+                background_color: Color::rgb(0.15, 0.15, 0.15).into(),
+                visibility: Visibility { is_visible: false },
+                ..default()
+            })
+            .insert(LoadingBar)
+            .with_children(|parent| {
+                parent
+                    .spawn(NodeBundle {
+                        style: Style {
+                            size: Size::new(Val::Percent(0.0), Val::Percent(100.0)),
+                            ..default()
+                        },
+                        background_color: Color::rgb(0.8, 0.8, 0.8).into(),
+                        ..default()
+                    })
+                    .insert(LoadingBarFill);
+            });
+    });
+}
+
+// Uses the raw time so fades still run while paused
+fn update_fade(
+    time: Res<Time>,
+    state: Res<State<AppState>>,
+    mut transition: ResMut<LevelTransition>,
+    mut overlays: Query<&mut BackgroundColor, With<FadeOverlay>>,
+) {
+    let target = match (transition.next, state.current()) {
+        (Some(_), _) | (None, AppState::Loading) => 1.0,
+        (None, AppState::Paused) => PAUSED_FADE,
+        (None, _) => 0.0,
+    };
+    let step = time.raw_delta_seconds() / FADE_TIME;
+    transition.fade = if transition.fade < target {
+        (transition.fade + step).min(target)
+    } else {
+        (transition.fade - step).max(target)
+    };
+    for mut color in overlays.iter_mut() {
+        color.0 = Color::rgba(0.0, 0.0, 0.0, transition.fade);
+    }
+}
+
+// Swaps the level once the screen is black
+#[allow(clippy::too_many_arguments)]
+fn switch_level(
+    mut com: Commands,
+    mut transition: ResMut<LevelTransition>,
+    mut state: ResMut<State<AppState>>,
+    mut level: ResMut<CurrentLevel>,
+    mut time: ResMut<Time>,
+    mut unloaded: EventWriter<LevelUnloaded>,
+    level_items: Query<Entity, With<LevelItem>>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    mut emissive_materials: ResMut<Assets<EmissiveMaterial>>,
+    asset_server: Res<AssetServer>,
+) {
+    let next = match transition.next {
+        Some(next) if transition.fade >= 1.0 => next,
+        _ => return,
+    };
+    transition.next = None;
+    for entity in level_items.iter() {
+        com.entity(entity).despawn_recursive();
+    }
+    if *level != CurrentLevel::None {
+        unloaded.send(LevelUnloaded(*level));
+    }
+    *level = next;
+    next.setup(
+        &mut com,
+        &mut custom_materials,
+        &mut emissive_materials,
+        &asset_server,
+    );
+    time.unpause();
+    // Fails if already there, e.g. going back to the menu from the menu
+    let _ = state.replace(AppState::for_level(next));
+}
+
+// Waits until every mesh and texture of the level has loaded (or failed to)
+#[allow(clippy::too_many_arguments)]
+fn track_loading(
+    asset_server: Res<AssetServer>,
+    mut progress: ResMut<LoadingProgress>,
+    mut state: ResMut<State<AppState>>,
+    mut loaded_events: EventWriter<LevelLoaded>,
+    level: Res<CurrentLevel>,
+    fog: Res<FogSettings>,
+    custom_materials: Res<Assets<CustomMaterial>>,
+    emissive_materials: Res<Assets<EmissiveMaterial>>,
+    meshes: Query<&Handle<Mesh>, With<LevelItem>>,
+    materials: Query<
+        (
+            Option<&Handle<CustomMaterial>>,
+            Option<&Handle<EmissiveMaterial>>,
+        ),
+        With<LevelItem>,
+    >,
+) {
+    let mut handles: HashSet<HandleId> = meshes.iter().map(|mesh| mesh.id()).collect();
+    for (custom, emissive) in materials.iter() {
+        if let Some(material) = custom.and_then(|handle| custom_materials.get(handle)) {
+            handles.extend(material.textures().map(|texture| texture.id()));
+        }
+        if let Some(material) = emissive.and_then(|handle| emissive_materials.get(handle)) {
+            handles.extend(material.emissive_texture.iter().map(|texture| texture.id()));
+        }
+    }
+    handles.extend(fog.sky.iter().map(|sky| sky.id()));
+    // Generated assets aren't loaded from disk
+    handles.retain(|id| matches!(id, HandleId::AssetPathId(_)));
+
+    let loaded = handles
+        .iter()
+        .filter(|&&id| {
+            matches!(
+                asset_server.get_load_state(id),
+                LoadState::Loaded | LoadState::Failed
+            )
+        })
+        .count();
+    *progress = LoadingProgress {
+        loaded,
+        total: handles.len(),
+    };
+    // The level is spawned with commands, so there's nothing to wait on in the first frame
+    if !meshes.is_empty() && loaded == handles.len() && state.set(AppState::InLevel).is_ok() {
+        loaded_events.send(LevelLoaded(*level));
+    }
+}
+
+fn update_loading_bar(
+    state: Res<State<AppState>>,
+    progress: Res<LoadingProgress>,
+    mut bars: Query<&mut Visibility, With<LoadingBar>>,
+    mut fills: Query<&mut Style, With<LoadingBarFill>>,
+) {
+    let loading = *state.current() == AppState::Loading;
+    for mut visibility in bars.iter_mut() {
+        if visibility.is_visible != loading {
+            visibility.is_visible = loading;
+        }
+    }
+    if loading {
+        for mut style in fills.iter_mut() {
+            style.size.width = Val::Percent(progress.fraction() * 100.0);
+        }
+    }
+}
+
+pub fn set_paused(state: &mut State<AppState>, time: &mut Time, paused: bool) {
+    let changed = if paused {
+        state.push(AppState::Paused)
+    } else {
+        state.pop()
+    };
+    if changed.is_ok() {
+        if paused {
+            time.pause();
+        } else {
+            time.unpause();
+        }
+    }
+}
+
+fn toggle_pause(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut state: ResMut<State<AppState>>,
+    mut time: ResMut<Time>,
+) {
+    // Only asks egui when the key was pressed, there's no egui context without a window
+    if !keys.just_pressed(PAUSE_KEY) || egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    match state.current() {
+        AppState::InLevel => set_paused(&mut state, &mut time, true),
+        AppState::Paused => set_paused(&mut state, &mut time, false),
+        _ => (),
+    }
+}
+
+// F5 reloads the level, F6 loads the next one
+fn level_hotkeys(
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    level: Res<CurrentLevel>,
+    mut load: EventWriter<LoadLevel>,
+) {
+    let reload = keys.just_pressed(RELOAD_KEY);
+    let next = keys.just_pressed(NEXT_LEVEL_KEY);
+    // Only asks egui when a key was pressed, there's no egui context without a window
+    if !(reload || next) || egui_context.ctx_mut().wants_keyboard_input() {
+        return;
+    }
+    let levels = CurrentLevel::LEVELS;
+    let current = levels.iter().position(|l| l == &*level);
+    if reload {
+        if current.is_some() {
+            load.send(LoadLevel(*level));
+        }
+    } else {
+        let next = current.map_or(0, |i| (i + 1) % levels.len());
+        load.send(LoadLevel(levels[next]));
+    }
+}
+
+// The main menu and the pause menu, should run after load_level_events
+pub fn state_menus(
+    mut egui_context: ResMut<EguiContext>,
+    mut state: ResMut<State<AppState>>,
+    mut time: ResMut<Time>,
+    transition: Res<LevelTransition>,
+    mut load: EventWriter<LoadLevel>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // Hidden while fading out
+    if transition.pending() {
+        return;
+    }
+    let (title, paused) = match state.current() {
+        AppState::Menu => ("Bevy Baked GI Demo", false),
+        AppState::Paused => ("Paused", true),
+        _ => return,
+    };
+    egui::Window::new(title)
+        .anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+        .collapsible(false)
+        .resizable(false)
+        .show(egui_context.ctx_mut(), |ui| {
+            if paused {
+                if ui.button("Resume").clicked() {
+                    set_paused(&mut state, &mut time, false);
+                }
+                if ui.button("Main Menu").clicked() {
+                    load.send(LoadLevel(CurrentLevel::None));
+                }
+            } else {
+                for level in CurrentLevel::LEVELS {
+                    if ui.button(format!("Load {}", level.label())).clicked() {
+                        load.send(LoadLevel(level));
+                    }
+                }
+            }
+            if ui.button("Quit").clicked() {
+                exit.send(AppExit);
+            }
+        });
+}
+
+// For the Settings window, which would be drawn over the loading screen
+pub fn not_loading(state: Res<State<AppState>>) -> ShouldRun {
+    if *state.current() == AppState::Loading {
+        ShouldRun::No
+    } else {
+        ShouldRun::Yes
+    }
+}
+
+// Level loading, fades, the loading screen and pausing. Starts in the menu until a LoadLevel
+pub struct AppStatePlugin;
+
+impl Plugin for AppStatePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_state(AppState::Menu)
+            .init_resource::<LevelTransition>()
+            .init_resource::<LoadingProgress>()
+            .add_event::<LoadLevel>()
+            .add_event::<LevelLoaded>()
+            .add_event::<LevelUnloaded>()
+            .add_startup_system(spawn_overlay)
+            .add_system(load_level_events)
+            .add_system(update_fade.after(load_level_events))
+            .add_system(switch_level.after(update_fade))
+            // Before the switch, which leaves the old level's entities until its commands are applied
+            .add_system_set(
+                SystemSet::on_update(AppState::Loading)
+                    .with_system(track_loading.before(switch_level)),
+            )
+            .add_system(update_loading_bar)
+            .add_system(toggle_pause)
+            .add_system(level_hotkeys);
+    }
+}
@@ -0,0 +1,401 @@
+use std::sync::{Arc, Mutex};
+
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::core_3d,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        camera::ExtractedCamera,
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntry, BindingResource, BindingType, Buffer, BufferBindingType,
+            BufferDescriptor, BufferUsages, CachedComputePipelineId, ComputePassDescriptor,
+            ComputePipelineDescriptor, MapMode, PipelineCache, ShaderStages, TextureSampleType,
+            TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice},
+        view::ViewTarget,
+        Extract, RenderApp, RenderStage,
+    },
+};
+
+use crate::system_timings::timed;
+use crate::tonemapping::{CameraExposure, DEFAULT_EV100, EXPOSURE_NODE, MAX_EV100, MIN_EV100};
+use crate::PlayerCamera;
+
+pub const LUMINANCE_HISTOGRAM_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x5c1e0b8a37d26f94);
+
+// Must match assets/shaders/luminance_histogram.wgsl
+pub const HISTOGRAM_BINS: usize = 64;
+pub const MIN_LOG2_LUMINANCE: f32 = -10.0;
+pub const MAX_LOG2_LUMINANCE: f32 = 6.0;
+
+const HISTOGRAM_SIZE: u64 = (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u64;
+
+// The darkest and brightest pixels are left out of the average, so a bright sky or a black
+// corner doesn't pull the exposure around
+const LOW_PERCENTILE: f32 = 0.1;
+const HIGH_PERCENTILE: f32 = 0.9;
+
+// log2(0.18), middle grey before tone mapping
+const TARGET_LOG2_LUMINANCE: f32 = -2.47;
+
+// Mean log2 luminance of the pixels between the low and high percentiles
+pub fn metered_log2_luminance(bins: &[u32; HISTOGRAM_BINS]) -> Option<f32> {
+    let total: u32 = bins.iter().sum();
+    if total == 0 {
+        return None;
+    }
+    let low = total as f32 * LOW_PERCENTILE;
+    let high = total as f32 * HIGH_PERCENTILE;
+    let bin_width = (MAX_LOG2_LUMINANCE - MIN_LOG2_LUMINANCE) / HISTOGRAM_BINS as f32;
+    let mut below = 0.0;
+    let mut sum = 0.0;
+    let mut weight = 0.0;
+    for (i, &count) in bins.iter().enumerate() {
+        let start = below;
+        below += count as f32;
+        // The part of this bin that's inside the percentiles
+        let inside = below.min(high) - start.max(low);
+        if inside > 0.0 {
+            sum += inside * (MIN_LOG2_LUMINANCE + (i as f32 + 0.5) * bin_width);
+            weight += inside;
+        }
+    }
+    if weight > 0.0 {
+        Some(sum / weight)
+    } else {
+        None
+    }
+}
+
+// Written by the render world once a readback finishes, taken by auto_exposure
+#[derive(Resource, Clone, Default)]
+pub struct LuminanceHistogram(Arc<Mutex<Option<[u32; HISTOGRAM_BINS]>>>);
+
+impl LuminanceHistogram {
+    pub fn take(&self) -> Option<[u32; HISTOGRAM_BINS]> {
+        self.0.lock().unwrap().take()
+    }
+
+    fn set(&self, bins: [u32; HISTOGRAM_BINS]) {
+        *self.0.lock().unwrap() = Some(bins);
+    }
+}
+
+// Moves ev100 towards the exposure that brings the metered luminance to middle grey.
+// Only the PlayerCamera's histogram is read back.
+pub fn auto_exposure(
+    time: Res<Time>,
+    histogram: Res<LuminanceHistogram>,
+    mut target_ev100: Local<Option<f32>>,
+    mut cameras: Query<&mut CameraExposure, With<PlayerCamera>>,
+) {
+    let bins = histogram.take();
+    if let Some(mut exposure) = cameras.iter_mut().next() {
+        if !exposure.auto {
+            *target_ev100 = None;
+            return;
+        }
+        // The histogram is of the scene before exposure, so the target doesn't depend on the
+        // current ev100. It's a few frames old, which the smoothing below hides.
+        if let Some(luminance) = bins.as_ref().and_then(metered_log2_luminance) {
+            let target = DEFAULT_EV100 + luminance - TARGET_LOG2_LUMINANCE - exposure.compensation;
+            *target_ev100 = Some(target.clamp(MIN_EV100, MAX_EV100));
+        }
+        if let Some(target) = *target_ev100 {
+            let t = 1.0 - (-exposure.adaptation_speed * time.delta_seconds()).exp();
+            let ev100 = exposure.ev100 + (target - exposure.ev100) * t;
+            if exposure.ev100 != ev100 {
+                exposure.ev100 = ev100;
+            }
+        }
+    }
+}
+
+// Marks the view whose histogram is read back
+#[derive(Component)]
+struct AutoExposureView;
+
+fn extract_auto_exposure(
+    mut commands: Commands,
+    cameras: Extract<Query<(Entity, &Camera, &CameraExposure), With<PlayerCamera>>>,
+) {
+    for (entity, camera, exposure) in cameras.iter() {
+        if camera.is_active && exposure.auto {
+            commands.get_or_spawn(entity).insert(AutoExposureView);
+        }
+    }
+}
+
+// The histogram buffer is copied to the readback buffer, which is mapped after the frame is
+// submitted and read at the start of a later frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    Copied,
+    Mapping,
+    Mapped,
+}
+
+#[derive(Resource)]
+struct LuminanceHistogramPipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedComputePipelineId,
+    histogram: Buffer,
+    readback_buffer: Buffer,
+    readback: Arc<Mutex<Readback>>,
+}
+
+impl FromWorld for LuminanceHistogramPipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("luminance_histogram_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let histogram = render_device.create_buffer(&BufferDescriptor {
+            label: Some("luminance_histogram"),
+            size: HISTOGRAM_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let readback_buffer = render_device.create_buffer(&BufferDescriptor {
+            label: Some("luminance_histogram_readback"),
+            size: HISTOGRAM_SIZE,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let pipeline = world
+            .resource_mut::<PipelineCache>()
+            .queue_compute_pipeline(ComputePipelineDescriptor {
+                label: Some("luminance_histogram_pipeline".into()),
+                layout: Some(vec![layout.clone()]),
+                shader: LUMINANCE_HISTOGRAM_SHADER_HANDLE.typed(),
+                shader_defs: vec![],
+                entry_point: "build_histogram".into(),
+            });
+        LuminanceHistogramPipeline {
+            layout,
+            pipeline,
+            histogram,
+            readback_buffer,
+            readback: Arc::new(Mutex::new(Readback::Idle)),
+        }
+    }
+}
+
+// Runs before the ExposureNode so the histogram is of the linear scene color
+struct LuminanceHistogramNode {
+    query: QueryState<(&'static ViewTarget, &'static ExtractedCamera), With<AutoExposureView>>,
+}
+
+impl LuminanceHistogramNode {
+    const IN_VIEW: &'static str = "view";
+    const NAME: &'static str = "luminance_histogram";
+
+    fn new(world: &mut World) -> Self {
+        LuminanceHistogramNode {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for LuminanceHistogramNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Self::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let (target, camera) = match self.query.get_manual(world, view_entity) {
+            Ok(result) => result,
+            Err(_) => return Ok(()),
+        };
+        let size = match camera.physical_target_size {
+            Some(size) => size,
+            None => return Ok(()),
+        };
+        let histogram = world.resource::<LuminanceHistogramPipeline>();
+        let pipeline = match world
+            .resource::<PipelineCache>()
+            .get_compute_pipeline(histogram.pipeline)
+        {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+        // The readback buffer can't be written while the last histogram is still in it
+        let mut readback = histogram.readback.lock().unwrap();
+        if *readback != Readback::Idle {
+            return Ok(());
+        }
+
+        let bind_group = render_context
+            .render_device
+            .create_bind_group(&BindGroupDescriptor {
+                label: Some("luminance_histogram_bind_group"),
+                layout: &histogram.layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(target.main_texture()),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: histogram.histogram.as_entire_binding(),
+                    },
+                ],
+            });
+        let encoder = &mut render_context.command_encoder;
+        encoder.clear_buffer(&histogram.histogram, 0, None);
+        {
+            let mut pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("luminance_histogram_pass"),
+            });
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, &bind_group, &[]);
+            pass.dispatch_workgroups((size.x + 15) / 16, (size.y + 15) / 16, 1);
+        }
+        encoder.copy_buffer_to_buffer(
+            &histogram.histogram,
+            0,
+            &histogram.readback_buffer,
+            0,
+            HISTOGRAM_SIZE,
+        );
+        *readback = Readback::Copied;
+        Ok(())
+    }
+}
+
+// In the cleanup stage, after the frame with the copy was submitted
+fn map_histogram_readback(
+    histogram: Res<LuminanceHistogramPipeline>,
+    render_device: Res<RenderDevice>,
+) {
+    {
+        let mut readback = histogram.readback.lock().unwrap();
+        if *readback != Readback::Copied {
+            return;
+        }
+        *readback = Readback::Mapping;
+    }
+    // The callback runs while wgpu polls the device on a later submit
+    let readback = histogram.readback.clone();
+    render_device.map_buffer(
+        &histogram.readback_buffer.slice(..),
+        MapMode::Read,
+        move |result| {
+            *readback.lock().unwrap() = if result.is_ok() {
+                Readback::Mapped
+            } else {
+                Readback::Idle
+            };
+        },
+    );
+}
+
+fn read_histogram_readback(
+    histogram: Res<LuminanceHistogramPipeline>,
+    shared: Res<LuminanceHistogram>,
+) {
+    let mut readback = histogram.readback.lock().unwrap();
+    if *readback != Readback::Mapped {
+        return;
+    }
+    let mut bins = [0u32; HISTOGRAM_BINS];
+    {
+        let data = histogram.readback_buffer.slice(..).get_mapped_range();
+        for (bin, bytes) in bins.iter_mut().zip(data.chunks_exact(4)) {
+            *bin = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]);
+        }
+    }
+    histogram.readback_buffer.unmap();
+    shared.set(bins);
+    *readback = Readback::Idle;
+}
+
+// Histogram based auto exposure for the PlayerCamera, enabled with CameraExposure::auto
+pub struct AutoExposurePlugin;
+
+impl Plugin for AutoExposurePlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            LUMINANCE_HISTOGRAM_SHADER_HANDLE,
+            "../assets/shaders/luminance_histogram.wgsl",
+            Shader::from_wgsl
+        );
+        let histogram = LuminanceHistogram::default();
+        app.insert_resource(histogram.clone())
+            .add_system(timed(auto_exposure));
+
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+        render_app
+            .insert_resource(histogram)
+            .init_resource::<LuminanceHistogramPipeline>()
+            .add_system_to_stage(RenderStage::Extract, extract_auto_exposure)
+            .add_system_to_stage(RenderStage::Prepare, read_histogram_readback)
+            .add_system_to_stage(RenderStage::Cleanup, map_histogram_readback);
+
+        let node = LuminanceHistogramNode::new(&mut render_app.world);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        let graph = render_graph
+            .get_sub_graph_mut(core_3d::graph::NAME)
+            .unwrap();
+        graph.add_node(LuminanceHistogramNode::NAME, node);
+        graph
+            .add_slot_edge(
+                graph.input_node().unwrap().id,
+                core_3d::graph::input::VIEW_ENTITY,
+                LuminanceHistogramNode::NAME,
+                LuminanceHistogramNode::IN_VIEW,
+            )
+            .unwrap();
+        graph
+            .add_node_edge(
+                core_3d::graph::node::MAIN_PASS,
+                LuminanceHistogramNode::NAME,
+            )
+            .unwrap();
+        graph
+            .add_node_edge(LuminanceHistogramNode::NAME, EXPOSURE_NODE)
+            .unwrap();
+    }
+}
@@ -0,0 +1,62 @@
+use bevy::{prelude::*, reflect::TypeUuid};
+
+use crate::data_texture::{DataTexture, DataTexturePlugin};
+use crate::system_timings::timed;
+
+// Further baked lights are treated as dynamic ones
+pub const MAX_BAKED_LIGHTS: usize = 16;
+
+// A point or spot light that's already in the lightmap. CustomMaterial leaves it out of its
+// dynamic lights, other materials like the planets' are still lit by it.
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct BakedLight;
+
+// Texel 0 is (count, 0, 0, 0), the positions of the baked lights follow.
+// The shader matches lights by position, bevy doesn't keep any other per light data.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct BakedLightData {
+    pub texels: [Vec4; MAX_BAKED_LIGHTS + 1],
+}
+
+impl Default for BakedLightData {
+    fn default() -> Self {
+        BakedLightData {
+            texels: [Vec4::ZERO; MAX_BAKED_LIGHTS + 1],
+        }
+    }
+}
+
+// Bound as CustomMaterial::baked_lights
+impl DataTexture for BakedLightData {
+    const HANDLE: HandleUntyped =
+        HandleUntyped::weak_from_u64(Image::TYPE_UUID, 0x1f6b39d7c48e2a05);
+
+    fn texels(&self) -> &[Vec4] {
+        &self.texels
+    }
+}
+
+pub fn sync_baked_lights(
+    mut data: ResMut<BakedLightData>,
+    lights: Query<&GlobalTransform, With<BakedLight>>,
+) {
+    let mut next = BakedLightData::default();
+    let mut count = 0;
+    for (texel, transform) in next.texels[1..].iter_mut().zip(lights.iter()) {
+        *texel = transform.translation().extend(0.0);
+        count += 1;
+    }
+    next.texels[0].x = count as f32;
+    if *data != next {
+        *data = next;
+    }
+}
+
+pub struct BakedLightsPlugin;
+
+impl Plugin for BakedLightsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(DataTexturePlugin::<BakedLightData>::default())
+            .add_system(timed(sync_baked_lights));
+    }
+}
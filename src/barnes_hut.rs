@@ -0,0 +1,285 @@
+use bevy::prelude::*;
+
+// Bodies closer than this many subdivisions are lumped into a single leaf
+const MAX_DEPTH: usize = 24;
+// Depth first traversal pushes at most 8 children per level
+const STACK_SIZE: usize = MAX_DEPTH * 8 + 1;
+
+#[derive(Clone, Copy, Debug)]
+struct Node {
+    center: Vec3,
+    half_size: f32,
+    mass: f32,
+    weighted_position: Vec3,
+    count: u32,
+    // Position of the body when this is a leaf holding exactly one
+    body_position: Vec3,
+    // Index of the first of 8 consecutive children
+    children: Option<u32>,
+}
+
+impl Node {
+    fn new(center: Vec3, half_size: f32) -> Self {
+        Node {
+            center,
+            half_size,
+            mass: 0.0,
+            weighted_position: Vec3::ZERO,
+            count: 0,
+            body_position: Vec3::ZERO,
+            children: None,
+        }
+    }
+
+    fn octant(&self, position: Vec3) -> usize {
+        (position.x >= self.center.x) as usize
+            | ((position.y >= self.center.y) as usize) << 1
+            | ((position.z >= self.center.z) as usize) << 2
+    }
+
+    fn add(&mut self, position: Vec3, mass: f32) {
+        if self.count == 0 {
+            self.body_position = position;
+        }
+        self.mass += mass;
+        self.weighted_position += position * mass;
+        self.count += 1;
+    }
+
+    fn center_of_mass(&self) -> Vec3 {
+        self.weighted_position / self.mass
+    }
+}
+
+// Octree for approximating n-body gravity in O(n log n).
+// The node buffer is kept between frames so rebuilding doesn't allocate.
+#[derive(Default)]
+pub struct Octree {
+    nodes: Vec<Node>,
+}
+
+impl Octree {
+    // bodies is called twice, once to find the bounds and once to insert
+    pub fn build<I: Iterator<Item = (Vec3, f32)>>(&mut self, bodies: impl Fn() -> I) {
+        self.nodes.clear();
+
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for (position, _) in bodies() {
+            min = min.min(position);
+            max = max.max(position);
+        }
+        if min.x > max.x {
+            return;
+        }
+        let half_size = ((max - min).max_element() * 0.5).max(0.001);
+        self.nodes.push(Node::new((min + max) * 0.5, half_size));
+
+        for (position, mass) in bodies() {
+            self.insert(position, mass);
+        }
+    }
+
+    fn subdivide(&mut self, index: usize) -> usize {
+        let parent = self.nodes[index];
+        let first = self.nodes.len();
+        let quarter = parent.half_size * 0.5;
+        for octant in 0..8 {
+            let offset = Vec3::new(
+                if octant & 1 != 0 { quarter } else { -quarter },
+                if octant & 2 != 0 { quarter } else { -quarter },
+                if octant & 4 != 0 { quarter } else { -quarter },
+            );
+            self.nodes.push(Node::new(parent.center + offset, quarter));
+        }
+        self.nodes[index].children = Some(first as u32);
+        first
+    }
+
+    fn insert(&mut self, position: Vec3, mass: f32) {
+        let mut index = 0;
+        let mut depth = 0;
+        loop {
+            let node = self.nodes[index];
+            self.nodes[index].add(position, mass);
+            if let Some(first) = node.children {
+                index = first as usize + node.octant(position);
+                depth += 1;
+                continue;
+            }
+            if node.count == 0 || depth >= MAX_DEPTH {
+                return;
+            }
+            // Leaf already held one body, push it down a level and keep descending
+            let first = self.subdivide(index);
+            self.nodes[first + node.octant(node.body_position)].add(node.body_position, node.mass);
+            index = first + node.octant(position);
+            depth += 1;
+        }
+    }
+
+    // theta is the opening angle, nodes that appear smaller than it are treated as a single mass.
+    // f is called with the offset from the source to position, the squared distance,
+    // the source mass and whether it's a single body (or a lumped leaf).
+    fn for_each_source(&self, position: Vec3, theta: f32, mut f: impl FnMut(Vec3, f32, f32, bool)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut stack = [0u32; STACK_SIZE];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len] as usize];
+            if node.count == 0 {
+                continue;
+            }
+            let leaf_body = node.children.is_none() && node.count == 1;
+            let other = if leaf_body {
+                node.body_position
+            } else {
+                node.center_of_mass()
+            };
+            let difference = position - other;
+            let distance_squared = difference.length_squared();
+            if leaf_body && distance_squared == 0.0 {
+                // This is the body itself
+                continue;
+            }
+            match node.children {
+                Some(first) if node.half_size * 2.0 >= theta * distance_squared.sqrt() => {
+                    for child in 0..8 {
+                        stack[len] = first + child;
+                        len += 1;
+                    }
+                }
+                Some(_) => f(difference, distance_squared, node.mass, false),
+                None => f(difference, distance_squared, node.mass, true),
+            }
+        }
+    }
+
+    // Bodies use the same softening as the direct sum did,
+    // the other body's mass squared is the minimum squared distance.
+    pub fn acceleration(&self, position: Vec3, theta: f32) -> Vec3 {
+        let mut acceleration = Vec3::ZERO;
+        self.for_each_source(
+            position,
+            theta,
+            |difference, distance_squared, mass, leaf| {
+                if leaf {
+                    acceleration -= difference.normalize_or_zero()
+                        * ((1.0 / mass.powf(2.0).max(distance_squared)) * mass);
+                } else {
+                    acceleration -= difference.normalize() * (mass / distance_squared);
+                }
+            },
+        );
+        acceleration
+    }
+
+    // Gravitational potential per unit mass, consistent with the softened acceleration.
+    // Inside the softening radius the pull is a constant 1/mass.
+    pub fn potential(&self, position: Vec3, theta: f32) -> f32 {
+        let mut potential = 0.0;
+        self.for_each_source(position, theta, |_, distance_squared, mass, leaf| {
+            let distance = distance_squared.sqrt();
+            if leaf && distance < mass {
+                potential += distance / mass - 2.0;
+            } else {
+                potential -= mass / distance;
+            }
+        });
+        potential
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bodies() -> Vec<(Vec3, f32)> {
+        (0..50)
+            .map(|i| {
+                let i = i as f32;
+                let position = Vec3::new((i * 1.3).sin(), (i * 2.1).cos(), (i * 0.7).sin()) * 20.0;
+                (position, 0.5 + (i * 0.37).fract())
+            })
+            .collect()
+    }
+
+    fn build(bodies: &[(Vec3, f32)]) -> Octree {
+        let mut octree = Octree::default();
+        octree.build(|| bodies.iter().copied());
+        octree
+    }
+
+    // The softened direct sum the octree approximates
+    fn direct_acceleration(bodies: &[(Vec3, f32)], position: Vec3) -> Vec3 {
+        let mut acceleration = Vec3::ZERO;
+        for &(other, mass) in bodies {
+            let difference = position - other;
+            acceleration -= difference.normalize_or_zero()
+                * ((1.0 / mass.powf(2.0).max(difference.length_squared())) * mass);
+        }
+        acceleration
+    }
+
+    #[test]
+    fn empty_tree_has_no_pull() {
+        let octree = build(&[]);
+        assert_eq!(octree.acceleration(Vec3::ONE, 0.5), Vec3::ZERO);
+        assert_eq!(octree.potential(Vec3::ONE, 0.5), 0.0);
+    }
+
+    #[test]
+    fn body_doesnt_pull_itself() {
+        let octree = build(&[(Vec3::new(1.0, 2.0, 3.0), 2.0)]);
+        assert_eq!(
+            octree.acceleration(Vec3::new(1.0, 2.0, 3.0), 0.5),
+            Vec3::ZERO
+        );
+    }
+
+    #[test]
+    fn zero_theta_matches_direct_sum() {
+        let bodies = bodies();
+        let octree = build(&bodies);
+        for &(position, _) in &bodies {
+            let expected = direct_acceleration(&bodies, position);
+            let actual = octree.acceleration(position, 0.0);
+            assert!(
+                (expected - actual).length() <= expected.length() * 1e-4 + 1e-6,
+                "{} != {}",
+                expected,
+                actual
+            );
+        }
+    }
+
+    #[test]
+    fn opening_angle_stays_close_to_direct_sum() {
+        let bodies = bodies();
+        let octree = build(&bodies);
+        let position = Vec3::new(100.0, -50.0, 30.0);
+        let expected = direct_acceleration(&bodies, position);
+        let actual = octree.acceleration(position, 0.5);
+        assert!((expected - actual).length() < expected.length() * 0.05);
+    }
+
+    #[test]
+    fn far_potential_is_newtonian() {
+        let bodies = [(Vec3::ZERO, 1.0), (Vec3::X * 4.0, 2.0)];
+        let octree = build(&bodies);
+        let position = Vec3::new(0.0, 10.0, 0.0);
+        let expected = -1.0 / 10.0 - 2.0 / position.distance(Vec3::X * 4.0);
+        assert!((octree.potential(position, 0.0) - expected).abs() < 1e-5);
+    }
+
+    #[test]
+    fn coincident_bodies_stop_subdividing() {
+        // Would recurse forever without MAX_DEPTH
+        let octree = build(&[(Vec3::ZERO, 1.0), (Vec3::ZERO, 1.0), (Vec3::X, 1.0)]);
+        assert!(octree.nodes.len() <= 1 + 8 * MAX_DEPTH);
+        assert_eq!(octree.nodes[0].count, 3);
+    }
+}
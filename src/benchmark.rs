@@ -0,0 +1,360 @@
+use std::{f32::consts::TAU, fmt::Write, time::Instant};
+
+use bevy::{app::AppExit, prelude::*};
+use serde::Serialize;
+
+use crate::app_state::AppState;
+use crate::camera_path::{load_camera_path, CameraKeyframe, CameraPath};
+use crate::level_camera::{apply_level_camera, LevelCamera};
+#[cfg(feature = "planets")]
+use crate::planets::{PlanetSettings, PlanetSpawnConfig};
+use crate::system_timings::SystemTimings;
+use crate::walk_camera::{CameraMode, WalkSettings};
+use crate::{CurrentLevel, PlayerCamera};
+
+// The camera path is sampled at this fixed rate, so every run sees the same views
+const BENCHMARK_FRAME_TIME: f32 = 1.0 / 60.0;
+// Used when the level has no recorded camera path
+const ORBIT_DURATION: f32 = 20.0;
+const ORBIT_KEYFRAMES: usize = 16;
+
+#[derive(Resource, Debug, Clone)]
+pub struct Benchmark {
+    pub level: CurrentLevel,
+    // No window and no renderer, for machines without a GPU
+    pub headless: bool,
+    // Measured frames, 0 plays the camera path once
+    pub frames: u32,
+    // Frames skipped once the level has loaded, before measuring starts
+    pub warmup: u32,
+    pub seed: u64,
+    // The report is written to <output>.json and <output>.csv
+    pub output: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    // Waiting for the level meshes
+    Loading,
+    Warmup(u32),
+    Measuring(u32),
+    Done,
+}
+
+#[derive(Resource, Debug)]
+struct BenchmarkRun {
+    phase: Phase,
+    path: CameraPath,
+    frames: u32,
+    // CPU time of every measured frame in seconds
+    frame_times: Vec<f32>,
+    last_frame: Option<Instant>,
+}
+
+// Circles the spawn point's look at target, for levels without a recorded path
+fn orbit_path(level_camera: &LevelCamera) -> CameraPath {
+    let target = level_camera.look_at;
+    let offset = level_camera.position - target;
+    let radius = Vec2::new(offset.x, offset.z).length().max(1.0);
+    let start = offset.z.atan2(offset.x);
+    let keyframes = (0..=ORBIT_KEYFRAMES)
+        .map(|i| {
+            let t = i as f32 / ORBIT_KEYFRAMES as f32;
+            let angle = start + t * TAU;
+            let position = target + Vec3::new(angle.cos() * radius, offset.y, angle.sin() * radius);
+            let transform = Transform::from_translation(position).looking_at(target, Vec3::Y);
+            CameraKeyframe::new(t * ORBIT_DURATION, &transform)
+        })
+        .collect();
+    CameraPath { keyframes }
+}
+
+fn start_benchmark(benchmark: Res<Benchmark>) {
+    info!("Benchmarking {:?}", benchmark);
+}
+
+#[allow(clippy::too_many_arguments)]
+fn run_benchmark(
+    benchmark: Res<Benchmark>,
+    mut run: ResMut<BenchmarkRun>,
+    timings: Res<SystemTimings>,
+    level_camera: Res<LevelCamera>,
+    state: Res<State<AppState>>,
+    mut cameras: Query<&mut Transform, With<PlayerCamera>>,
+    mut exit: EventWriter<AppExit>,
+) {
+    let phase = run.phase;
+    match phase {
+        Phase::Loading => {
+            // Waits for the loading screen, so every mesh and texture is in
+            if *state.current() != AppState::InLevel {
+                return;
+            }
+            let path = benchmark
+                .level
+                .name()
+                .and_then(|name| load_camera_path(name).ok())
+                .filter(|path| path.keyframes.len() > 1);
+            run.path = match path {
+                Some(path) => path,
+                None => {
+                    info!("No camera path recorded for this level, orbiting the spawn point");
+                    orbit_path(&level_camera)
+                }
+            };
+            run.frames = if benchmark.frames > 0 {
+                benchmark.frames
+            } else {
+                (run.path.duration() / BENCHMARK_FRAME_TIME).ceil() as u32 + 1
+            };
+            run.phase = Phase::Warmup(benchmark.warmup);
+        }
+        Phase::Warmup(0) => {
+            timings.clear();
+            run.frame_times.clear();
+            run.phase = Phase::Measuring(0);
+        }
+        Phase::Warmup(n) => run.phase = Phase::Warmup(n - 1),
+        Phase::Measuring(frame) if frame + 1 >= run.frames => {
+            run.phase = Phase::Done;
+            let report = BenchmarkReport::new(&benchmark, &mut run.frame_times, &timings);
+            info!(
+                "Benchmark done, frame time mean {:.3}ms p99 {:.3}ms",
+                report.frame_time.mean_ms, report.frame_time.p99_ms
+            );
+            if let Err(e) = report.write(&benchmark.output) {
+                error!(
+                    "Failed to write benchmark report to {}: {}",
+                    benchmark.output, e
+                );
+            }
+            exit.send(AppExit);
+            return;
+        }
+        Phase::Measuring(frame) => run.phase = Phase::Measuring(frame + 1),
+        Phase::Done => return,
+    }
+
+    // Warmup holds the first view, measuring steps along the path at a fixed rate
+    let frame = match run.phase {
+        Phase::Measuring(frame) => frame,
+        _ => 0,
+    };
+    let duration = run.path.duration();
+    let mut time = frame as f32 * BENCHMARK_FRAME_TIME;
+    // Loops when more frames were asked for than the path is long
+    if duration > 0.0 && time > duration {
+        time %= duration;
+    }
+    if let (Some(sampled), Ok(mut transform)) = (run.path.sample(time), cameras.get_single_mut()) {
+        transform.translation = sampled.translation;
+        transform.rotation = sampled.rotation;
+    }
+}
+
+// Runs last, so the time between two runs covers the whole frame including rendering
+fn record_frame_time(mut run: ResMut<BenchmarkRun>) {
+    let now = Instant::now();
+    if let (Phase::Measuring(_), Some(last)) = (run.phase, run.last_frame) {
+        run.frame_times.push((now - last).as_secs_f32());
+    }
+    run.last_frame = Some(now);
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+struct Stats {
+    runs: usize,
+    mean_ms: f32,
+    min_ms: f32,
+    p50_ms: f32,
+    p90_ms: f32,
+    p95_ms: f32,
+    p99_ms: f32,
+    max_ms: f32,
+    // Total time spent per measured frame, systems on a fixed timestep may run more than once
+    per_frame_ms: f32,
+}
+
+impl Stats {
+    fn new(samples: &mut [f32], frames: usize) -> Self {
+        if samples.is_empty() {
+            return Stats::default();
+        }
+        samples.sort_unstable_by(|a, b| a.total_cmp(b));
+        let ms = |seconds: f32| seconds * 1000.0;
+        let percentile = |p: f32| {
+            let i = (p / 100.0 * (samples.len() - 1) as f32).round() as usize;
+            ms(samples[i])
+        };
+        let total: f32 = samples.iter().sum();
+        Stats {
+            runs: samples.len(),
+            mean_ms: ms(total / samples.len() as f32),
+            min_ms: ms(samples[0]),
+            p50_ms: percentile(50.0),
+            p90_ms: percentile(90.0),
+            p95_ms: percentile(95.0),
+            p99_ms: percentile(99.0),
+            max_ms: ms(samples[samples.len() - 1]),
+            per_frame_ms: ms(total / frames.max(1) as f32),
+        }
+    }
+
+    fn csv_row(&self, out: &mut String, name: &str) {
+        let _ = writeln!(
+            out,
+            "\"{}\",{},{},{},{},{},{},{},{},{}",
+            name.replace('"', "\"\""),
+            self.runs,
+            self.mean_ms,
+            self.min_ms,
+            self.p50_ms,
+            self.p90_ms,
+            self.p95_ms,
+            self.p99_ms,
+            self.max_ms,
+            self.per_frame_ms
+        );
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct SystemReport {
+    name: String,
+    #[serde(flatten)]
+    stats: Stats,
+}
+
+#[derive(Serialize, Debug, Clone)]
+struct BenchmarkReport {
+    level: String,
+    headless: bool,
+    seed: u64,
+    frames: usize,
+    frame_time: Stats,
+    // Slowest first
+    systems: Vec<SystemReport>,
+}
+
+impl BenchmarkReport {
+    fn new(benchmark: &Benchmark, frame_times: &mut [f32], timings: &SystemTimings) -> Self {
+        let frames = frame_times.len();
+        let mut systems: Vec<SystemReport> = timings
+            .take()
+            .into_iter()
+            .map(|(name, mut samples)| SystemReport {
+                name: name.into_owned(),
+                stats: Stats::new(&mut samples, frames),
+            })
+            .collect();
+        systems.sort_by(|a, b| b.stats.per_frame_ms.total_cmp(&a.stats.per_frame_ms));
+        BenchmarkReport {
+            level: benchmark.level.name().unwrap_or_default().to_string(),
+            headless: benchmark.headless,
+            seed: benchmark.seed,
+            frames,
+            frame_time: Stats::new(frame_times, frames),
+            systems,
+        }
+    }
+
+    fn write(&self, output: &str) -> Result<(), String> {
+        if let Some(parent) = std::path::Path::new(output).parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        std::fs::write(format!("{}.json", output), json).map_err(|e| e.to_string())?;
+
+        let mut csv = String::from(
+            "name,runs,mean_ms,min_ms,p50_ms,p90_ms,p95_ms,p99_ms,max_ms,per_frame_ms\n",
+        );
+        self.frame_time.csv_row(&mut csv, "frame");
+        for system in &self.systems {
+            system.stats.csv_row(&mut csv, &system.name);
+        }
+        std::fs::write(format!("{}.csv", output), csv).map_err(|e| e.to_string())?;
+        info!("Wrote {}.json and {}.csv", output, output);
+        Ok(())
+    }
+}
+
+// Loads the level, flies the camera along its path and writes a frame time report, then exits
+pub struct BenchmarkPlugin(pub Benchmark);
+
+impl Plugin for BenchmarkPlugin {
+    fn build(&self, app: &mut App) {
+        #[cfg(feature = "planets")]
+        {
+            app.world
+                .get_resource_or_insert_with(PlanetSpawnConfig::default)
+                .seed = self.0.seed;
+            // The camera path is sampled at BENCHMARK_FRAME_TIME, the planets have to keep up
+            // with it instead of the clock so every run simulates the same
+            app.world
+                .get_resource_or_insert_with(PlanetSettings::default)
+                .step_every_frame = true;
+        }
+        // Walk mode would fight the path for the camera, Fly without a controller leaves it alone
+        app.world
+            .get_resource_or_insert_with(WalkSettings::default)
+            .mode = CameraMode::Fly;
+        app.insert_resource(self.0.clone())
+            .init_resource::<SystemTimings>()
+            .insert_resource(BenchmarkRun {
+                phase: Phase::Loading,
+                path: CameraPath::default(),
+                frames: 0,
+                frame_times: Vec::new(),
+                last_frame: None,
+            })
+            .add_startup_system(start_benchmark)
+            .add_system(run_benchmark.after(apply_level_camera))
+            .add_system_to_stage(CoreStage::Last, record_frame_time);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn empty_samples() {
+        let stats = Stats::new(&mut [], 10);
+        assert_eq!(stats.runs, 0);
+        assert_eq!(stats.max_ms, 0.0);
+    }
+
+    #[test]
+    fn percentiles_of_unsorted_samples() {
+        // 1ms to 101ms, shuffled
+        let mut samples: Vec<f32> = (0..101)
+            .map(|i| ((i * 37) % 101 + 1) as f32 / 1000.0)
+            .collect();
+        let stats = Stats::new(&mut samples, 101);
+        let near = |a: f32, b: f32| (a - b).abs() < 1e-3;
+        assert_eq!(stats.runs, 101);
+        assert!(near(stats.min_ms, 1.0));
+        assert!(near(stats.p50_ms, 51.0));
+        assert!(near(stats.p90_ms, 91.0));
+        assert!(near(stats.p95_ms, 96.0));
+        assert!(near(stats.p99_ms, 100.0));
+        assert!(near(stats.max_ms, 101.0));
+        assert!(near(stats.mean_ms, 51.0));
+        assert!(near(stats.per_frame_ms, 51.0));
+    }
+
+    #[test]
+    fn single_sample() {
+        let stats = Stats::new(&mut [0.004], 1);
+        assert_eq!(stats.p50_ms, stats.p99_ms);
+        assert_eq!(stats.min_ms, stats.max_ms);
+    }
+
+    #[test]
+    fn per_frame_counts_repeated_runs() {
+        // A fixed timestep system that ran twice in each of 2 frames
+        let stats = Stats::new(&mut [0.001, 0.001, 0.001, 0.001], 2);
+        assert!((stats.per_frame_ms - 2.0).abs() < 1e-4);
+        assert!((stats.mean_ms - 1.0).abs() < 1e-4);
+    }
+}
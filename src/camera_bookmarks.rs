@@ -0,0 +1,231 @@
+use bevy::prelude::*;
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+use crate::ron_file::{load_ron, save_ron};
+use crate::walk_camera::Walker;
+use crate::{CurrentLevel, PlayerCamera};
+
+// Bookmarks 1 to 9 are jumped to with the number keys
+const HOTKEYS: [KeyCode; 9] = [
+    KeyCode::Key1,
+    KeyCode::Key2,
+    KeyCode::Key3,
+    KeyCode::Key4,
+    KeyCode::Key5,
+    KeyCode::Key6,
+    KeyCode::Key7,
+    KeyCode::Key8,
+    KeyCode::Key9,
+];
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CameraBookmark {
+    pub name: String,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl CameraBookmark {
+    fn new(name: String, transform: &Transform) -> Self {
+        CameraBookmark {
+            name,
+            position: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+        }
+    }
+
+    fn transform(&self) -> Transform {
+        Transform::from_translation(Vec3::from(self.position))
+            .with_rotation(Quat::from_array(self.rotation).normalize())
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct Transition {
+    from: Transform,
+    to: Transform,
+    t: f32,
+}
+
+// Changes that need the camera transform, applied by camera_bookmarks_system
+#[derive(Clone, Copy, Debug)]
+enum BookmarkEdit {
+    Add,
+    Set(usize),
+    Go(usize),
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct CameraBookmarks {
+    pub bookmarks: Vec<CameraBookmark>,
+    // Seconds it takes to fly to a bookmark, 0.0 jumps straight there
+    pub transition_time: f32,
+    new_name: String,
+    transition: Option<Transition>,
+    pending: Option<BookmarkEdit>,
+    // Saved to the level's bookmark file at the end of the frame
+    dirty: bool,
+}
+
+impl Default for CameraBookmarks {
+    fn default() -> Self {
+        CameraBookmarks {
+            bookmarks: Vec::new(),
+            transition_time: 0.75,
+            new_name: String::new(),
+            transition: None,
+            pending: None,
+            dirty: false,
+        }
+    }
+}
+
+pub fn bookmarks_file(level: &str) -> String {
+    format!("assets/levels/{}/bookmarks.ron", level)
+}
+
+impl CameraBookmarks {
+    pub fn transitioning(&self) -> bool {
+        self.transition.is_some()
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui, level: &CurrentLevel) {
+        if level.name().is_none() {
+            ui.label("Load a level to use bookmarks");
+            return;
+        }
+        ui.add(egui::Slider::new(&mut self.transition_time, 0.0..=3.0).text("transition_time"));
+        let mut remove = None;
+        for (i, bookmark) in self.bookmarks.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let hotkey = if i < HOTKEYS.len() {
+                    format!("{}", i + 1)
+                } else {
+                    String::from("-")
+                };
+                ui.label(hotkey);
+                self.dirty |= ui.text_edit_singleline(&mut bookmark.name).lost_focus();
+                if ui.button("Go").clicked() {
+                    self.pending = Some(BookmarkEdit::Go(i));
+                }
+                if ui.button("Set").clicked() {
+                    self.pending = Some(BookmarkEdit::Set(i));
+                }
+                if ui.button("X").clicked() {
+                    remove = Some(i);
+                }
+            });
+        }
+        if let Some(i) = remove {
+            self.bookmarks.remove(i);
+            self.dirty = true;
+        }
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.new_name);
+            if ui.button("Add Bookmark").clicked() {
+                self.pending = Some(BookmarkEdit::Add);
+            }
+        });
+    }
+}
+
+fn smoothstep(t: f32) -> f32 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+pub fn camera_bookmarks_system(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mut egui_context: ResMut<EguiContext>,
+    mut bookmarks: ResMut<CameraBookmarks>,
+    mut cameras: Query<(&mut Transform, Option<&mut Walker>), With<PlayerCamera>>,
+) {
+    let (mut transform, walker) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let bookmarks = &mut *bookmarks;
+
+    // Only asks egui when a hotkey was pressed, there's no egui context without a window
+    let mut go = HOTKEYS
+        .iter()
+        .position(|key| keys.just_pressed(*key))
+        .filter(|&i| i < bookmarks.bookmarks.len());
+    if go.is_some() && egui_context.ctx_mut().wants_keyboard_input() {
+        go = None;
+    }
+    match bookmarks.pending.take() {
+        Some(BookmarkEdit::Add) => {
+            let name = if bookmarks.new_name.is_empty() {
+                format!("Bookmark {}", bookmarks.bookmarks.len() + 1)
+            } else {
+                std::mem::take(&mut bookmarks.new_name)
+            };
+            bookmarks
+                .bookmarks
+                .push(CameraBookmark::new(name, &transform));
+            bookmarks.dirty = true;
+        }
+        Some(BookmarkEdit::Set(i)) => {
+            if let Some(bookmark) = bookmarks.bookmarks.get_mut(i) {
+                *bookmark = CameraBookmark::new(std::mem::take(&mut bookmark.name), &transform);
+                bookmarks.dirty = true;
+            }
+        }
+        Some(BookmarkEdit::Go(i)) => go = Some(i),
+        None => (),
+    }
+
+    if let Some(bookmark) = go.and_then(|i| bookmarks.bookmarks.get(i)) {
+        bookmarks.transition = Some(Transition {
+            from: *transform,
+            to: bookmark.transform(),
+            t: 0.0,
+        });
+    }
+
+    if let Some(transition) = bookmarks.transition.as_mut() {
+        if bookmarks.transition_time > 0.0 {
+            transition.t += time.delta_seconds() / bookmarks.transition_time;
+        } else {
+            transition.t = 1.0;
+        }
+        let t = smoothstep(transition.t.min(1.0));
+        transform.translation = transition
+            .from
+            .translation
+            .lerp(transition.to.translation, t);
+        transform.rotation = transition.from.rotation.slerp(transition.to.rotation, t);
+        if transition.t >= 1.0 {
+            bookmarks.transition = None;
+        }
+        // Walk mode picks up the new orientation and starts from rest
+        if let Some(mut walker) = walker {
+            walker.reset();
+        }
+    }
+}
+
+// Bookmarks are kept per level, load them when the level changes and save them when edited
+pub fn level_camera_bookmarks(level: Res<CurrentLevel>, mut bookmarks: ResMut<CameraBookmarks>) {
+    if level.is_changed() {
+        bookmarks.transition = None;
+        bookmarks.dirty = false;
+        bookmarks.bookmarks = level
+            .name()
+            .and_then(|name| load_ron(bookmarks_file(name)).ok())
+            .unwrap_or_default();
+        return;
+    }
+    if !bookmarks.dirty {
+        return;
+    }
+    bookmarks.dirty = false;
+    if let Some(name) = level.name() {
+        let file = bookmarks_file(name);
+        if let Err(e) = save_ron(&file, &bookmarks.bookmarks) {
+            error!("Failed to save camera bookmarks to {}: {}", file, e);
+        }
+    }
+}
@@ -0,0 +1,423 @@
+use bevy::prelude::*;
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::ron_file::{load_ron, save_ron};
+use crate::walk_camera::Walker;
+use crate::{CurrentLevel, PlayerCamera};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct CameraKeyframe {
+    // Seconds from the start of the path
+    pub time: f32,
+    pub position: [f32; 3],
+    pub rotation: [f32; 4],
+}
+
+impl CameraKeyframe {
+    pub fn new(time: f32, transform: &Transform) -> Self {
+        CameraKeyframe {
+            time,
+            position: transform.translation.to_array(),
+            rotation: transform.rotation.to_array(),
+        }
+    }
+
+    pub fn position(&self) -> Vec3 {
+        Vec3::from(self.position)
+    }
+
+    pub fn rotation(&self) -> Quat {
+        Quat::from_array(self.rotation).normalize()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct CameraPath {
+    // Sorted by time
+    pub keyframes: Vec<CameraKeyframe>,
+}
+
+fn catmull_rom(p0: Vec3, p1: Vec3, p2: Vec3, p3: Vec3, t: f32) -> Vec3 {
+    let t2 = t * t;
+    let t3 = t2 * t;
+    0.5 * (2.0 * p1
+        + (p2 - p0) * t
+        + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t2
+        + (3.0 * p1 - p0 - 3.0 * p2 + p3) * t3)
+}
+
+impl CameraPath {
+    pub fn duration(&self) -> f32 {
+        self.keyframes.last().map_or(0.0, |key| key.time)
+    }
+
+    pub fn sort(&mut self) {
+        self.keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+    }
+
+    // Catmull-Rom through the keyframe positions, rotations are slerped
+    pub fn sample(&self, time: f32) -> Option<Transform> {
+        let keys = &self.keyframes;
+        let last = keys.len().checked_sub(1)?;
+        if last == 0 {
+            return Some(
+                Transform::from_translation(keys[0].position()).with_rotation(keys[0].rotation()),
+            );
+        }
+        let time = time.clamp(keys[0].time, keys[last].time);
+        let b = keys.partition_point(|key| key.time <= time).clamp(1, last);
+        let a = b - 1;
+        let span = keys[b].time - keys[a].time;
+        let t = if span > 0.0 {
+            (time - keys[a].time) / span
+        } else {
+            1.0
+        };
+        let position = catmull_rom(
+            keys[a.saturating_sub(1)].position(),
+            keys[a].position(),
+            keys[b].position(),
+            keys[(b + 1).min(last)].position(),
+            t,
+        );
+        let rotation = keys[a].rotation().slerp(keys[b].rotation(), t);
+        Some(Transform::from_translation(position).with_rotation(rotation))
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Easing {
+    Linear,
+    // Accelerates at the start and slows down at the end of the path
+    EaseInOut,
+}
+
+impl Easing {
+    fn apply(&self, t: f32) -> f32 {
+        match self {
+            Easing::Linear => t,
+            Easing::EaseInOut => t * t * (3.0 - 2.0 * t),
+        }
+    }
+}
+
+// Changes that need the camera transform, applied by camera_path_system
+#[derive(Clone, Copy, Debug)]
+enum PathEdit {
+    // Appends the current camera as a keyframe
+    Add,
+    // Replaces a keyframe with the current camera
+    Set(usize),
+    // Moves the camera to a keyframe
+    Go(usize),
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct CameraPathPlayer {
+    pub path: CameraPath,
+    pub recording: bool,
+    // Seconds between recorded keyframes
+    pub record_interval: f32,
+    pub playing: bool,
+    pub looping: bool,
+    pub speed: f32,
+    pub easing: Easing,
+    // Playback or recording position in seconds
+    pub time: f32,
+    last_recorded: f32,
+    pending: Option<PathEdit>,
+}
+
+impl Default for CameraPathPlayer {
+    fn default() -> Self {
+        CameraPathPlayer {
+            path: CameraPath::default(),
+            recording: false,
+            record_interval: 0.5,
+            playing: false,
+            looping: false,
+            speed: 1.0,
+            easing: Easing::EaseInOut,
+            time: 0.0,
+            last_recorded: 0.0,
+            pending: None,
+        }
+    }
+}
+
+pub fn camera_path_file(level: &str) -> String {
+    format!("assets/levels/{}/camera_path.ron", level)
+}
+
+// Sorted, since sample expects it and the file may have been edited by hand
+pub fn load_camera_path(level: &str) -> Result<CameraPath, String> {
+    let mut path: CameraPath = load_ron(camera_path_file(level))?;
+    path.sort();
+    Ok(path)
+}
+
+impl CameraPathPlayer {
+    pub fn play(&mut self) {
+        self.recording = false;
+        self.playing = self.path.keyframes.len() > 1;
+        self.time = 0.0;
+    }
+
+    pub fn start_recording(&mut self) {
+        self.playing = false;
+        self.recording = true;
+        self.path.keyframes.clear();
+        self.time = 0.0;
+        self.last_recorded = f32::NEG_INFINITY;
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui, level: &CurrentLevel) {
+        ui.horizontal(|ui| {
+            if self.recording {
+                if ui.button("Stop Recording").clicked() {
+                    self.recording = false;
+                }
+            } else if ui.button("Record").clicked() {
+                self.start_recording();
+            }
+            if self.playing {
+                if ui.button("Stop").clicked() {
+                    self.playing = false;
+                }
+            } else if ui.button("Play").clicked() {
+                self.play();
+            }
+            ui.checkbox(&mut self.looping, "loop");
+        });
+        ui.add(egui::Slider::new(&mut self.record_interval, 0.05..=5.0).text("record_interval"));
+        ui.add(egui::Slider::new(&mut self.speed, 0.1..=5.0).text("speed"));
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.easing, Easing::Linear, "Linear");
+            ui.selectable_value(&mut self.easing, Easing::EaseInOut, "Ease In/Out");
+        });
+        ui.label(format!(
+            "{} keyframes, {:.2}s",
+            self.path.keyframes.len(),
+            self.path.duration()
+        ));
+
+        let mut remove = None;
+        let mut resort = false;
+        egui::ScrollArea::vertical()
+            .max_height(200.0)
+            .show(ui, |ui| {
+                for (i, key) in self.path.keyframes.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.label(format!("{}", i));
+                        resort |= ui
+                            .add(
+                                egui::DragValue::new(&mut key.time)
+                                    .speed(0.05)
+                                    .clamp_range(0.0..=f32::MAX)
+                                    .suffix("s"),
+                            )
+                            .changed();
+                        if ui.button("Go").clicked() {
+                            self.pending = Some(PathEdit::Go(i));
+                        }
+                        if ui.button("Set").clicked() {
+                            self.pending = Some(PathEdit::Set(i));
+                        }
+                        if ui.button("X").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+            });
+        if let Some(i) = remove {
+            self.path.keyframes.remove(i);
+        }
+        if resort {
+            self.path.sort();
+        }
+        if ui.button("Add Keyframe").clicked() {
+            self.pending = Some(PathEdit::Add);
+        }
+
+        if let Some(name) = level.name() {
+            let file = camera_path_file(name);
+            ui.horizontal(|ui| {
+                if ui.button("Save").clicked() {
+                    match save_ron(&file, &self.path) {
+                        Ok(()) => info!("Saved camera path to {}", file),
+                        Err(e) => error!("Failed to save camera path to {}: {}", file, e),
+                    }
+                }
+                if ui.button("Load").clicked() {
+                    match load_camera_path(name) {
+                        Ok(path) => self.path = path,
+                        Err(e) => error!("Failed to load camera path from {}: {}", file, e),
+                    }
+                }
+            });
+        }
+    }
+}
+
+// Records, plays back and applies UI edits to the camera path
+pub fn camera_path_system(
+    time: Res<Time>,
+    mut player: ResMut<CameraPathPlayer>,
+    mut cameras: Query<(&mut Transform, Option<&mut Walker>), With<PlayerCamera>>,
+) {
+    let (mut transform, mut walker) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    let player = &mut *player;
+
+    if let Some(edit) = player.pending.take() {
+        let keys = &mut player.path.keyframes;
+        match edit {
+            PathEdit::Add => {
+                let time = keys
+                    .last()
+                    .map_or(0.0, |key| key.time + player.record_interval);
+                keys.push(CameraKeyframe::new(time, &transform));
+            }
+            PathEdit::Set(i) => {
+                if let Some(key) = keys.get_mut(i) {
+                    *key = CameraKeyframe::new(key.time, &transform);
+                }
+            }
+            PathEdit::Go(i) => {
+                if let Some(key) = keys.get(i) {
+                    transform.translation = key.position();
+                    transform.rotation = key.rotation();
+                    if let Some(walker) = walker.as_mut() {
+                        walker.reset();
+                    }
+                }
+            }
+        }
+    }
+
+    let dt = time.delta_seconds();
+    if player.recording {
+        if player.time - player.last_recorded >= player.record_interval {
+            player.last_recorded = player.time;
+            let key = CameraKeyframe::new(player.time, &transform);
+            player.path.keyframes.push(key);
+        }
+        player.time += dt;
+    } else if player.playing {
+        let duration = player.path.duration();
+        player.time += dt * player.speed;
+        if player.time >= duration {
+            if player.looping && duration > 0.0 {
+                player.time %= duration;
+            } else {
+                player.time = duration;
+                player.playing = false;
+            }
+        }
+        let progress = if duration > 0.0 {
+            player.time / duration
+        } else {
+            1.0
+        };
+        if let Some(sampled) = player.path.sample(player.easing.apply(progress) * duration) {
+            transform.translation = sampled.translation;
+            transform.rotation = sampled.rotation;
+        }
+        // So walking carries on from wherever playback stops
+        if let Some(walker) = walker.as_mut() {
+            walker.reset();
+        }
+    }
+}
+
+// Each level has its own path, load it (or start empty) when the level changes
+pub fn load_level_camera_path(level: Res<CurrentLevel>, mut player: ResMut<CameraPathPlayer>) {
+    if !level.is_changed() {
+        return;
+    }
+    player.playing = false;
+    player.recording = false;
+    player.path = level
+        .name()
+        .and_then(|name| load_camera_path(name).ok())
+        .unwrap_or_default();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(time: f32, position: Vec3, rotation: Quat) -> CameraKeyframe {
+        CameraKeyframe::new(
+            time,
+            &Transform::from_translation(position).with_rotation(rotation),
+        )
+    }
+
+    fn assert_near(a: Vec3, b: Vec3) {
+        assert!(a.distance(b) < 1e-4, "{} != {}", a, b);
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_inner_points() {
+        let (p0, p1, p2, p3) = (Vec3::ZERO, Vec3::X, Vec3::new(2.0, 1.0, 0.0), Vec3::Z);
+        assert_near(catmull_rom(p0, p1, p2, p3, 0.0), p1);
+        assert_near(catmull_rom(p0, p1, p2, p3, 1.0), p2);
+    }
+
+    #[test]
+    fn catmull_rom_is_linear_on_evenly_spaced_points() {
+        let t = 0.3;
+        let point = catmull_rom(Vec3::ZERO, Vec3::X, Vec3::X * 2.0, Vec3::X * 3.0, t);
+        assert_near(point, Vec3::X * (1.0 + t));
+    }
+
+    #[test]
+    fn empty_path_has_no_sample() {
+        assert!(CameraPath::default().sample(0.0).is_none());
+    }
+
+    #[test]
+    fn single_keyframe_is_held() {
+        let path = CameraPath {
+            keyframes: vec![key(1.0, Vec3::ONE, Quat::IDENTITY)],
+        };
+        assert_near(path.sample(5.0).unwrap().translation, Vec3::ONE);
+    }
+
+    #[test]
+    fn sample_hits_keyframes_and_clamps() {
+        let path = CameraPath {
+            keyframes: vec![
+                key(0.0, Vec3::ZERO, Quat::IDENTITY),
+                key(1.0, Vec3::new(1.0, 2.0, 0.0), Quat::from_rotation_y(0.5)),
+                key(3.0, Vec3::new(4.0, 0.0, 1.0), Quat::from_rotation_y(1.0)),
+            ],
+        };
+        for keyframe in &path.keyframes {
+            let sample = path.sample(keyframe.time).unwrap();
+            assert_near(sample.translation, keyframe.position());
+            assert!(sample.rotation.angle_between(keyframe.rotation()) < 1e-3);
+        }
+        assert_near(path.sample(-1.0).unwrap().translation, Vec3::ZERO);
+        assert_near(
+            path.sample(10.0).unwrap().translation,
+            Vec3::new(4.0, 0.0, 1.0),
+        );
+    }
+
+    #[test]
+    fn rotation_is_slerped() {
+        let path = CameraPath {
+            keyframes: vec![
+                key(0.0, Vec3::ZERO, Quat::IDENTITY),
+                key(2.0, Vec3::X, Quat::from_rotation_y(1.0)),
+            ],
+        };
+        let rotation = path.sample(1.0).unwrap().rotation;
+        assert!(rotation.angle_between(Quat::from_rotation_y(0.5)) < 1e-3);
+    }
+}
@@ -0,0 +1,231 @@
+use bevy::prelude::*;
+
+use baked_gi::app_settings::AppSettings;
+use baked_gi::benchmark::Benchmark;
+use baked_gi::CurrentLevel;
+
+pub const USAGE: &str = "usage: material_demo [options]
+    --level <name>            level to load on start (level1, level2)
+    --window-size <w>x<h>     window size in logical pixels
+    --vsync, --no-vsync       vsync is always off for benchmarks
+    --planets <count>         number of planets spawned
+    --no-ui                   run without the Settings window and menus
+    --benchmark <level>       measure frame times along the level's camera path and exit
+      --headless              without a window or renderer
+      --frames <n>            measured frames, defaults to the length of the camera path
+      --warmup <n>            frames skipped before measuring, defaults to 120
+      --seed <n>              planet seed, defaults to 0
+      --output <path>         report path without extension, defaults to benchmark/<level>";
+
+// Anything given here overrides the saved AppSettings for this run
+#[derive(Debug, Clone, Default)]
+pub struct CliArgs {
+    pub level: Option<CurrentLevel>,
+    pub window_size: Option<Vec2>,
+    pub vsync: Option<bool>,
+    pub planets: Option<u32>,
+    pub no_ui: bool,
+    pub benchmark: Option<Benchmark>,
+}
+
+fn parse_value<T: std::str::FromStr>(
+    name: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<T, String> {
+    let value = args
+        .next()
+        .ok_or_else(|| format!("{} needs a value", name))?;
+    value
+        .parse()
+        .map_err(|_| format!("invalid value for {}: {}", name, value))
+}
+
+fn parse_level(
+    name: &str,
+    args: &mut impl Iterator<Item = String>,
+) -> Result<CurrentLevel, String> {
+    let level: String = parse_value(name, args)?;
+    CurrentLevel::from_name(&level).ok_or_else(|| format!("unknown level: {}", level))
+}
+
+fn parse_size(name: &str, args: &mut impl Iterator<Item = String>) -> Result<Vec2, String> {
+    let size: String = parse_value(name, args)?;
+    let invalid = || {
+        format!(
+            "invalid value for {}: {}, expected <width>x<height>",
+            name, size
+        )
+    };
+    let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+    match (width.parse::<f32>(), height.parse::<f32>()) {
+        (Ok(width), Ok(height)) if width > 0.0 && height > 0.0 => Ok(Vec2::new(width, height)),
+        _ => Err(invalid()),
+    }
+}
+
+impl CliArgs {
+    // Ok(None) when the usage was asked for with --help
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Option<CliArgs>, String> {
+        let mut cli = CliArgs::default();
+        let mut benchmark_level = None;
+        let mut headless = false;
+        let mut frames = 0;
+        let mut warmup = 120;
+        let mut seed = 0;
+        let mut output = None;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--help" | "-h" => return Ok(None),
+                "--level" => cli.level = Some(parse_level(&arg, &mut args)?),
+                "--window-size" => cli.window_size = Some(parse_size(&arg, &mut args)?),
+                "--vsync" => cli.vsync = Some(true),
+                "--no-vsync" => cli.vsync = Some(false),
+                "--planets" => cli.planets = Some(parse_value(&arg, &mut args)?),
+                "--no-ui" => cli.no_ui = true,
+                "--benchmark" => benchmark_level = Some(parse_level(&arg, &mut args)?),
+                "--headless" => headless = true,
+                "--frames" => frames = parse_value(&arg, &mut args)?,
+                "--warmup" => warmup = parse_value(&arg, &mut args)?,
+                "--seed" => seed = parse_value(&arg, &mut args)?,
+                "--output" => output = Some(parse_value(&arg, &mut args)?),
+                _ => return Err(format!("unknown argument: {}", arg)),
+            }
+        }
+        cli.benchmark = match benchmark_level {
+            Some(level) => Some(Benchmark {
+                level,
+                headless,
+                frames,
+                warmup,
+                seed,
+                output: output
+                    .unwrap_or_else(|| format!("benchmark/{}", level.name().unwrap_or_default())),
+            }),
+            None if headless => return Err(String::from("--headless needs --benchmark")),
+            None => None,
+        };
+        Ok(Some(cli))
+    }
+
+    pub fn apply_to(&self, settings: &mut AppSettings) {
+        if let Some(level) = self.level {
+            settings.level = level.name().map(String::from);
+        }
+        if let Some(size) = self.window_size {
+            settings.window_width = size.x;
+            settings.window_height = size.y;
+        }
+        if let Some(vsync) = self.vsync {
+            settings.vsync = vsync;
+        }
+        if let Some(planets) = self.planets {
+            settings.planet_count = planets;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Option<CliArgs>, String> {
+        CliArgs::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn no_arguments() {
+        let cli = parse(&[]).unwrap().unwrap();
+        assert!(cli.level.is_none());
+        assert!(cli.benchmark.is_none());
+        assert!(!cli.no_ui);
+    }
+
+    #[test]
+    fn help() {
+        assert!(parse(&["--level", "level1", "--help"]).unwrap().is_none());
+        assert!(parse(&["-h"]).unwrap().is_none());
+    }
+
+    #[test]
+    fn options() {
+        let cli = parse(&[
+            "--level",
+            "level2",
+            "--window-size",
+            "1280x720",
+            "--no-vsync",
+            "--planets",
+            "12",
+            "--no-ui",
+        ])
+        .unwrap()
+        .unwrap();
+        assert_eq!(cli.level, Some(CurrentLevel::Level2));
+        assert_eq!(cli.window_size, Some(Vec2::new(1280.0, 720.0)));
+        assert_eq!(cli.vsync, Some(false));
+        assert_eq!(cli.planets, Some(12));
+        assert!(cli.no_ui);
+    }
+
+    #[test]
+    fn benchmark_defaults() {
+        let cli = parse(&["--benchmark", "level1"]).unwrap().unwrap();
+        let benchmark = cli.benchmark.unwrap();
+        assert_eq!(benchmark.level, CurrentLevel::Level1);
+        assert!(!benchmark.headless);
+        assert_eq!(benchmark.frames, 0);
+        assert_eq!(benchmark.warmup, 120);
+        assert_eq!(benchmark.seed, 0);
+        assert_eq!(benchmark.output, "benchmark/level1");
+    }
+
+    #[test]
+    fn benchmark_options() {
+        let cli = parse(&[
+            "--headless",
+            "--benchmark",
+            "level2",
+            "--frames",
+            "300",
+            "--warmup",
+            "10",
+            "--seed",
+            "7",
+            "--output",
+            "out/run",
+        ])
+        .unwrap()
+        .unwrap();
+        let benchmark = cli.benchmark.unwrap();
+        assert!(benchmark.headless);
+        assert_eq!(benchmark.frames, 300);
+        assert_eq!(benchmark.warmup, 10);
+        assert_eq!(benchmark.seed, 7);
+        assert_eq!(benchmark.output, "out/run");
+    }
+
+    #[test]
+    fn errors() {
+        assert!(parse(&["--bogus"]).is_err());
+        assert!(parse(&["--level"]).is_err());
+        assert!(parse(&["--level", "level3"]).is_err());
+        assert!(parse(&["--planets", "many"]).is_err());
+        assert!(parse(&["--window-size", "1280"]).is_err());
+        assert!(parse(&["--window-size", "0x720"]).is_err());
+        assert!(parse(&["--headless"]).is_err());
+    }
+
+    #[test]
+    fn overrides_settings() {
+        let mut settings = AppSettings::default();
+        let vsync = settings.vsync;
+        let cli = parse(&["--level", "level2", "--planets", "3"])
+            .unwrap()
+            .unwrap();
+        cli.apply_to(&mut settings);
+        assert_eq!(settings.level.as_deref(), Some("level2"));
+        assert_eq!(settings.planet_count, 3);
+        assert_eq!(settings.vsync, vsync);
+    }
+}
@@ -1,17 +1,24 @@
-use std::{num::NonZeroU8, ops::RangeInclusive};
+use std::{collections::HashMap, num::NonZeroU8, ops::RangeInclusive};
 
 use bevy::{
+    pbr::{MaterialPipeline, MaterialPipelineKey},
     prelude::*,
     reflect::TypeUuid,
     render::{
+        mesh::MeshVertexBufferLayout,
         render_resource::{
-            AddressMode, AsBindGroup, FilterMode, SamplerDescriptor, ShaderRef, ShaderType,
+            AddressMode, AsBindGroup, FilterMode, RenderPipelineDescriptor, SamplerDescriptor,
+            ShaderRef, ShaderType, SpecializedMeshPipelineError,
         },
         texture::ImageSampler,
     },
 };
 
 use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::fog::FogUniform;
+use crate::ron_file::load_ron;
 
 #[derive(ShaderType, Debug, Clone, Copy)]
 pub struct MaterialSetProp {
@@ -50,9 +57,12 @@ pub struct MaterialProperties {
     pub reflection: MaterialSetProp,
     pub walls: MaterialSetProp,
     pub reflection_mask: MaterialSetProp,
-    pub mist: MaterialSetProp,
+    // Only with a shadow mask, otherwise the sun is baked into the lightmap
     pub directional_light_blend: f32,
-    //pub directional_light_color: Vec3,
+    pub dynamic_light_blend: f32,
+    // With a shadow mask, real time shadows fade to the baked ones past this distance from the camera
+    pub shadow_distance: f32,
+    pub shadow_fade: f32,
 }
 
 impl MaterialProperties {
@@ -68,18 +78,133 @@ impl MaterialProperties {
         self.reflection.build_ui(ui, "reflection");
         self.reflection_mask.build_ui(ui, "reflection_mask");
         self.walls.build_ui(ui, "walls");
-        self.mist.build_ui(ui, "mist");
         ui.label("-------------");
         ui.add(
-            egui::Slider::new(&mut self.directional_light_blend, 0.0..=5.0)
+            egui::Slider::new(&mut self.directional_light_blend, 0.0..=1.0)
                 .text("directional_light_blend"),
         );
+        ui.add(
+            egui::Slider::new(&mut self.dynamic_light_blend, 0.0..=5.0).text("dynamic_light_blend"),
+        );
+        ui.add(egui::Slider::new(&mut self.shadow_distance, 0.0..=100.0).text("shadow_distance"));
+        ui.add(egui::Slider::new(&mut self.shadow_fade, 0.0..=50.0).text("shadow_fade"));
+    }
+}
+
+// Replaces the shaded output with a single term, selected with a shader def
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum DebugView {
+    #[default]
+    None,
+    Lightmap,
+    BaseA,
+    BaseB,
+    VaryA,
+    VaryB,
+    Reflection,
+    Walls,
+    PuddleMask,
+    WallsMask,
+    Fresnel,
+    Shadow,
+    Fog,
+    UvChecker,
+    TexelDensity,
+    Occlusion,
+}
+
+impl DebugView {
+    pub const ALL: [DebugView; 16] = [
+        DebugView::None,
+        DebugView::Lightmap,
+        DebugView::BaseA,
+        DebugView::BaseB,
+        DebugView::VaryA,
+        DebugView::VaryB,
+        DebugView::Reflection,
+        DebugView::Walls,
+        DebugView::PuddleMask,
+        DebugView::WallsMask,
+        DebugView::Fresnel,
+        DebugView::Shadow,
+        DebugView::Fog,
+        DebugView::UvChecker,
+        DebugView::TexelDensity,
+        DebugView::Occlusion,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            DebugView::None => "None",
+            DebugView::Lightmap => "Raw Lightmap",
+            DebugView::BaseA => "base_a",
+            DebugView::BaseB => "base_b",
+            DebugView::VaryA => "vary_a",
+            DebugView::VaryB => "vary_b",
+            DebugView::Reflection => "reflection",
+            DebugView::Walls => "walls",
+            DebugView::PuddleMask => "Puddle Mask",
+            DebugView::WallsMask => "Walls Mask",
+            DebugView::Fresnel => "Fresnel",
+            DebugView::Shadow => "Shadow",
+            DebugView::Fog => "Fog",
+            DebugView::UvChecker => "UV Checker",
+            DebugView::TexelDensity => "Lightmap Texel Density",
+            DebugView::Occlusion => "Dynamic Occlusion",
+        }
+    }
+
+    pub fn shader_def(&self) -> Option<&'static str> {
+        match self {
+            DebugView::None => None,
+            DebugView::Lightmap => Some("DEBUG_LIGHTMAP"),
+            DebugView::BaseA => Some("DEBUG_BASE_A"),
+            DebugView::BaseB => Some("DEBUG_BASE_B"),
+            DebugView::VaryA => Some("DEBUG_VARY_A"),
+            DebugView::VaryB => Some("DEBUG_VARY_B"),
+            DebugView::Reflection => Some("DEBUG_REFLECTION"),
+            DebugView::Walls => Some("DEBUG_WALLS"),
+            DebugView::PuddleMask => Some("DEBUG_PUDDLE_MASK"),
+            DebugView::WallsMask => Some("DEBUG_WALLS_MASK"),
+            DebugView::Fresnel => Some("DEBUG_FRESNEL"),
+            DebugView::Shadow => Some("DEBUG_SHADOW"),
+            DebugView::Fog => Some("DEBUG_FOG"),
+            DebugView::UvChecker => Some("DEBUG_UV_CHECKER"),
+            DebugView::TexelDensity => Some("DEBUG_TEXEL_DENSITY"),
+            DebugView::Occlusion => Some("DEBUG_OCCLUSION"),
+        }
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_label("debug view")
+            .selected_text(self.name())
+            .show_ui(ui, |ui| {
+                for view in DebugView::ALL {
+                    ui.selectable_value(self, view, view.name());
+                }
+            });
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CustomMaterialKey {
+    debug_view: DebugView,
+    shadow_mask: bool,
+}
+
+impl From<&CustomMaterial> for CustomMaterialKey {
+    fn from(material: &CustomMaterial) -> Self {
+        CustomMaterialKey {
+            debug_view: material.debug_view,
+            shadow_mask: material.shadow_mask.is_some(),
+        }
     }
 }
 
 // This is the struct that will be passed to your shader
 #[derive(AsBindGroup, Debug, Clone, TypeUuid)]
 #[uuid = "4ee9c361-1124-4113-890e-197d82b00123"]
+#[bind_group_data(CustomMaterialKey)]
 pub struct CustomMaterial {
     #[uniform(0)]
     pub material_properties: MaterialProperties,
@@ -103,12 +228,44 @@ pub struct CustomMaterial {
     #[sampler(10)]
     pub walls: Option<Handle<Image>>,
     pub walls_path: String,
+    #[uniform(11)]
+    pub fog: FogUniform,
+    // Always OccluderData::texture(), shared by every material
+    #[texture(12, sample_type = "float", filterable = false)]
+    pub occluders: Handle<Image>,
+    // Baked sun visibility in the lightmap UVs, white is lit.
+    // When set the lightmap is expected to hold only indirect light and the sun is added at runtime.
+    #[texture(13)]
+    #[sampler(14)]
+    pub shadow_mask: Option<Handle<Image>>,
+    pub shadow_mask_path: String,
+    // Always BakedLightData::texture(), the lights left out of the dynamic lights
+    #[texture(15, sample_type = "float", filterable = false)]
+    pub baked_lights: Handle<Image>,
+    pub debug_view: DebugView,
 }
 
 impl Material for CustomMaterial {
     fn fragment_shader() -> ShaderRef {
         "shaders/custom_material.wgsl".into()
     }
+
+    fn specialize(
+        _pipeline: &MaterialPipeline<Self>,
+        descriptor: &mut RenderPipelineDescriptor,
+        _layout: &MeshVertexBufferLayout,
+        key: MaterialPipelineKey<Self>,
+    ) -> Result<(), SpecializedMeshPipelineError> {
+        if let Some(fragment) = descriptor.fragment.as_mut() {
+            if let Some(def) = key.bind_group_data.debug_view.shader_def() {
+                fragment.shader_defs.push(def.to_string());
+            }
+            if key.bind_group_data.shadow_mask {
+                fragment.shader_defs.push(String::from("SHADOW_MASK"));
+            }
+        }
+        Ok(())
+    }
 }
 
 fn load_button(
@@ -129,9 +286,75 @@ fn load_button(
 }
 
 impl CustomMaterial {
+    // Every texture that's set
+    pub fn textures(&self) -> impl Iterator<Item = &Handle<Image>> {
+        [
+            &self.lightmap,
+            &self.base,
+            &self.vary,
+            &self.reflection,
+            &self.walls,
+            &self.shadow_mask,
+        ]
+        .into_iter()
+        .flatten()
+    }
+
+    // Sets the lightmap and the level's shadow mask for it, if there's one
+    pub fn set_lightmap(&mut self, ass: &AssetServer, path: &str, shadow_masks: &ShadowMasks) {
+        self.lightmap = Some(ass.load(path));
+        self.lightmap_path = String::from(path);
+        match shadow_masks.masks.get(path) {
+            Some(mask) => {
+                self.shadow_mask = Some(ass.load(mask.as_str()));
+                self.shadow_mask_path = mask.clone();
+            }
+            None => {
+                self.shadow_mask = None;
+                self.shadow_mask_path = String::new();
+            }
+        }
+    }
+
+    // Everything but the lightmap and shadow mask, which are per mesh
+    pub fn copy_shared(&mut self, from: &CustomMaterial) {
+        self.material_properties = from.material_properties;
+        self.debug_view = from.debug_view;
+        self.base = from.base.clone();
+        self.base_path = from.base_path.clone();
+        self.vary = from.vary.clone();
+        self.vary_path = from.vary_path.clone();
+        self.reflection = from.reflection.clone();
+        self.reflection_path = from.reflection_path.clone();
+        self.walls = from.walls.clone();
+        self.walls_path = from.walls_path.clone();
+    }
+
+    // The textures every material of the level shares
     pub fn build_ui(&mut self, ui: &mut egui::Ui, com: &mut Commands, ass: &Res<AssetServer>) {
+        self.debug_view.build_ui(ui);
         self.material_properties.build_ui(ui);
         ui.label("CustomMaterial");
+        load_button(ui, com, ass, "base", &mut self.base_path, &mut self.base);
+        load_button(ui, com, ass, "vary", &mut self.vary_path, &mut self.vary);
+        load_button(
+            ui,
+            com,
+            ass,
+            "reflection",
+            &mut self.reflection_path,
+            &mut self.reflection,
+        );
+        load_button(ui, com, ass, "walls", &mut self.walls_path, &mut self.walls);
+    }
+
+    // The textures in this mesh's lightmap UVs
+    pub fn build_lightmap_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        com: &mut Commands,
+        ass: &Res<AssetServer>,
+    ) {
         load_button(
             ui,
             com,
@@ -140,17 +363,38 @@ impl CustomMaterial {
             &mut self.lightmap_path,
             &mut self.lightmap,
         );
-        load_button(ui, com, ass, "base", &mut self.base_path, &mut self.base);
-        load_button(ui, com, ass, "vary", &mut self.vary_path, &mut self.vary);
         load_button(
             ui,
             com,
             ass,
-            "reflection",
-            &mut self.reflection_path,
-            &mut self.reflection,
+            "shadow_mask",
+            &mut self.shadow_mask_path,
+            &mut self.shadow_mask,
         );
-        load_button(ui, com, ass, "walls", &mut self.walls_path, &mut self.walls);
+        if self.shadow_mask.is_some() && ui.button("CLEAR shadow_mask").clicked() {
+            self.shadow_mask = None;
+        }
+    }
+}
+
+pub fn shadow_masks_file(level: &str) -> String {
+    format!("assets/levels/{}/shadow_masks.ron", level)
+}
+
+// Baked sun visibility per lightmap, for levels whose lightmaps only have indirect light.
+// Levels without the file have the sun baked into their lightmaps.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct ShadowMasks {
+    // Lightmap path to shadow mask path, both relative to assets
+    pub masks: HashMap<String, String>,
+}
+
+impl ShadowMasks {
+    pub fn load(level: &str) -> Self {
+        match load_ron(shadow_masks_file(level)) {
+            Ok(masks) => masks,
+            Err(_) => ShadowMasks::default(),
+        }
     }
 }
 
@@ -0,0 +1,116 @@
+use std::{marker::PhantomData, num::NonZeroU32};
+
+use bevy::{
+    prelude::*,
+    render::{
+        render_asset::{PrepareAssetLabel, RenderAssets},
+        render_resource::{
+            Extent3d, ImageCopyTexture, ImageDataLayout, Origin3d, TextureAspect, TextureDimension,
+            TextureFormat,
+        },
+        renderer::RenderQueue,
+        Extract, RenderApp, RenderStage,
+    },
+};
+
+// A row of Rgba32Float texels shared by every CustomMaterial. Changing it doesn't re-prepare
+// the materials, which is what per-material uniforms would do. Bound with
+// #[texture(n, sample_type = "float", filterable = false)] and read with textureLoad.
+pub trait DataTexture: Resource + Clone + Default {
+    const HANDLE: HandleUntyped;
+
+    fn texels(&self) -> &[Vec4];
+
+    fn texture() -> Handle<Image> {
+        Self::HANDLE.typed()
+    }
+}
+
+fn extract_data_texture<T: DataTexture>(mut commands: Commands, data: Extract<Res<T>>) {
+    if data.is_changed() {
+        commands.insert_resource(data.clone());
+    }
+}
+
+// Also written once the texture is first prepared
+fn write_data_texture<T: DataTexture>(
+    data: Option<Res<T>>,
+    images: Res<RenderAssets<Image>>,
+    render_queue: Res<RenderQueue>,
+    mut written: Local<bool>,
+) {
+    let (data, image) = match (data, images.get(&T::texture())) {
+        (Some(data), Some(image)) => (data, image),
+        _ => {
+            *written = false;
+            return;
+        }
+    };
+    if *written && !data.is_changed() {
+        return;
+    }
+    let texels = data.texels();
+    let bytes: Vec<u8> = texels
+        .iter()
+        .flat_map(|texel| texel.to_array())
+        .flat_map(f32::to_le_bytes)
+        .collect();
+    render_queue.write_texture(
+        ImageCopyTexture {
+            texture: &image.texture,
+            mip_level: 0,
+            origin: Origin3d::ZERO,
+            aspect: TextureAspect::All,
+        },
+        &bytes,
+        ImageDataLayout {
+            offset: 0,
+            bytes_per_row: NonZeroU32::new(bytes.len() as u32),
+            rows_per_image: None,
+        },
+        Extent3d {
+            width: texels.len() as u32,
+            height: 1,
+            depth_or_array_layers: 1,
+        },
+    );
+    *written = true;
+}
+
+// Adds the texture and the resource it's written from
+pub struct DataTexturePlugin<T>(PhantomData<fn() -> T>);
+
+impl<T> Default for DataTexturePlugin<T> {
+    fn default() -> Self {
+        DataTexturePlugin(PhantomData)
+    }
+}
+
+impl<T: DataTexture> Plugin for DataTexturePlugin<T> {
+    fn build(&self, app: &mut App) {
+        let data = T::default();
+        let texture = Image::new(
+            Extent3d {
+                width: data.texels().len() as u32,
+                height: 1,
+                depth_or_array_layers: 1,
+            },
+            TextureDimension::D2,
+            vec![0; data.texels().len() * 16],
+            TextureFormat::Rgba32Float,
+        );
+        app.world
+            .resource_mut::<Assets<Image>>()
+            .set_untracked(T::HANDLE, texture);
+        app.insert_resource(data);
+
+        if let Ok(render_app) = app.get_sub_app_mut(RenderApp) {
+            render_app
+                .add_system_to_stage(RenderStage::Extract, extract_data_texture::<T>)
+                .add_system_to_stage(
+                    RenderStage::Prepare,
+                    write_data_texture::<T>.after(PrepareAssetLabel::AssetPrepare),
+                );
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use bevy::{ecs::system::SystemParam, prelude::*, window::CursorGrabMode};
+use bevy_egui::{egui, EguiContext};
+
+use crate::app_settings::AppSettings;
+use crate::app_state::{not_loading, LoadLevel};
+use crate::camera_bookmarks::CameraBookmarks;
+use crate::camera_path::CameraPathPlayer;
+use crate::custom_material::{CustomMaterial, DebugView};
+#[cfg(feature = "fly-camera")]
+use crate::fly_camera::FlyCameraUi;
+use crate::fog::FogSettings;
+use crate::level_camera::LevelCamera;
+use crate::occluders::OccluderSettings;
+#[cfg(feature = "planets")]
+use crate::planets::PlanetUi;
+use crate::tonemapping::CameraExposure;
+use crate::walk_camera::WalkSettings;
+use crate::{CurrentLevel, PlayerCamera};
+
+// Everything the Settings window needs for the camera
+#[derive(SystemParam)]
+struct CameraUi<'w, 's> {
+    exposures: Query<'w, 's, &'static mut CameraExposure, With<PlayerCamera>>,
+    spawn: ResMut<'w, LevelCamera>,
+    walk: ResMut<'w, WalkSettings>,
+    path: ResMut<'w, CameraPathPlayer>,
+    bookmarks: ResMut<'w, CameraBookmarks>,
+}
+
+impl CameraUi<'_, '_> {
+    fn build_ui(&mut self, ui: &mut egui::Ui, level: &CurrentLevel) {
+        if let Some(mut exposure) = self.exposures.iter_mut().next() {
+            ui.collapsing("exposure", |ui| {
+                exposure.build_ui(ui);
+            });
+        }
+        ui.collapsing("camera spawn", |ui| {
+            self.spawn.build_ui(ui);
+        });
+        ui.collapsing("camera mode", |ui| {
+            self.walk.build_ui(ui);
+        });
+        ui.collapsing("camera path", |ui| {
+            self.path.build_ui(ui, level);
+        });
+        ui.collapsing("bookmarks", |ui| {
+            self.bookmarks.build_ui(ui, level);
+        });
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+fn menu_ui(
+    mut com: Commands,
+    mut windows: ResMut<Windows>,
+    mut egui_context: ResMut<EguiContext>,
+    mut custom_materials: ResMut<Assets<CustomMaterial>>,
+    mut material_handles: Query<&mut Handle<CustomMaterial>>,
+    mut load: EventWriter<LoadLevel>,
+    asset_server: Res<AssetServer>,
+    mut app_settings: ResMut<AppSettings>,
+    mut fog: ResMut<FogSettings>,
+    mut occluders: ResMut<OccluderSettings>,
+    mut camera: CameraUi,
+    level: Res<CurrentLevel>,
+    #[cfg(feature = "fly-camera")] mut fly: FlyCameraUi,
+    #[cfg(feature = "planets")] mut planets: PlanetUi,
+) {
+    let window = windows.get_primary_mut().unwrap();
+    let show_ui = window.is_focused() && !(window.cursor_grab_mode() == CursorGrabMode::Locked);
+    if show_ui {
+        egui::Window::new("Settings")
+            .open(&mut app_settings.show_settings)
+            .show(egui_context.ctx_mut(), |ui| {
+                ui.horizontal(|ui| {
+                    for next in CurrentLevel::LEVELS {
+                        if ui.button(format!("Load {}", next.label())).clicked() {
+                            load.send(LoadLevel(next));
+                        }
+                    }
+                    if ui.button("Main Menu").clicked() {
+                        load.send(LoadLevel(CurrentLevel::None));
+                    }
+                });
+                if let Some(handle) = material_handles.iter_mut().next() {
+                    let main_mat = if let Some(main_mat) = custom_materials.get_mut(&handle.clone())
+                    {
+                        ui.collapsing("material properties", |ui| {
+                            main_mat.build_ui(ui, &mut com, &asset_server);
+                        });
+                        Some(main_mat.clone())
+                    } else {
+                        None
+                    };
+                    if let Some(main_mat) = main_mat {
+                        for handle in material_handles.iter_mut() {
+                            if let Some(mat) = custom_materials.get_mut(&handle.clone()) {
+                                mat.copy_shared(&main_mat);
+                            }
+                        }
+                        // Debug views output raw values
+                        let raw = main_mat.debug_view != DebugView::None;
+                        if let Some(mut exposure) = camera.exposures.iter_mut().next() {
+                            if exposure.raw != raw {
+                                exposure.raw = raw;
+                            }
+                        }
+                    }
+                }
+                // Each mesh has its own lightmap UVs
+                ui.collapsing("lightmaps", |ui| {
+                    for (i, handle) in material_handles.iter().enumerate() {
+                        if let Some(mat) = custom_materials.get_mut(handle) {
+                            ui.push_id(i, |ui| {
+                                mat.build_lightmap_ui(ui, &mut com, &asset_server);
+                            });
+                            ui.separator();
+                        }
+                    }
+                });
+                ui.collapsing("fog", |ui| {
+                    fog.build_ui(ui);
+                });
+                ui.collapsing("dynamic occluders", |ui| {
+                    occluders.build_ui(ui);
+                });
+                camera.build_ui(ui, &level);
+                #[cfg(feature = "fly-camera")]
+                fly.build_ui(ui, &camera.walk);
+                #[cfg(feature = "planets")]
+                ui.collapsing("planets", |ui| {
+                    planets.build_ui(ui);
+                });
+            });
+    }
+}
+
+// F1 opens the Settings window again after it was closed
+fn toggle_settings_window(keys: Res<Input<KeyCode>>, mut app_settings: ResMut<AppSettings>) {
+    if keys.just_pressed(KeyCode::F1) {
+        app_settings.show_settings = !app_settings.show_settings;
+    }
+}
+
+// The Settings window for tweaking materials, fog, cameras and planets
+pub struct EditorUiPlugin;
+
+impl Plugin for EditorUiPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system(menu_ui.with_run_criteria(not_loading))
+            .add_system(toggle_settings_window);
+    }
+}
@@ -4,6 +4,8 @@ use bevy::{
     render::render_resource::{AsBindGroup, ShaderRef},
 };
 
+use crate::fog::FogUniform;
+
 // This is the struct that will be passed to your shader
 #[derive(AsBindGroup, Debug, Clone, TypeUuid)]
 #[uuid = "4ee9c361-1124-4113-890e-197d82b00321"]
@@ -13,6 +15,8 @@ pub struct EmissiveMaterial {
     #[texture(1)]
     #[sampler(2)]
     pub emissive_texture: Option<Handle<Image>>,
+    #[uniform(3)]
+    pub fog: FogUniform,
 }
 
 impl Material for EmissiveMaterial {
@@ -0,0 +1,117 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+use bevy_basic_camera::{CameraController, CameraControllerPlugin};
+use bevy_egui::{egui, EguiContext};
+
+use crate::app_settings::{AppSettings, ControllerSpeeds};
+use crate::camera_bookmarks::CameraBookmarks;
+use crate::camera_path::CameraPathPlayer;
+use crate::walk_camera::{CameraMode, WalkSettings};
+use crate::PlayerCamera;
+
+impl ControllerSpeeds {
+    pub fn from_controller(controller: &CameraController) -> Self {
+        ControllerSpeeds {
+            walk_speed: controller.walk_speed,
+            run_speed: controller.run_speed,
+            sensitivity: controller.sensitivity,
+        }
+    }
+
+    pub fn apply(&self, controller: &mut CameraController) {
+        controller.walk_speed = self.walk_speed;
+        controller.run_speed = self.run_speed;
+        controller.sensitivity = self.sensitivity;
+    }
+}
+
+// The fly camera speeds in the Settings window
+#[derive(SystemParam)]
+pub struct FlyCameraUi<'w, 's> {
+    controllers: Query<'w, 's, &'static mut CameraController>,
+}
+
+impl FlyCameraUi<'_, '_> {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui, walk: &WalkSettings) {
+        if walk.mode != CameraMode::Fly {
+            return;
+        }
+        if let Some(mut controller) = self.controllers.iter_mut().next() {
+            ui.collapsing("fly camera", |ui| {
+                ui.add(
+                    egui::Slider::new(&mut controller.walk_speed, 0.1..=100.0)
+                        .logarithmic(true)
+                        .text("walk_speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut controller.run_speed, 0.1..=100.0)
+                        .logarithmic(true)
+                        .text("run_speed"),
+                );
+                ui.add(
+                    egui::Slider::new(&mut controller.sensitivity, 0.01..=5.0)
+                        .logarithmic(true)
+                        .text("sensitivity"),
+                );
+            });
+        }
+    }
+}
+
+// With the saved speeds
+fn add_fly_controller(
+    mut com: Commands,
+    settings: Res<AppSettings>,
+    cameras: Query<Entity, Added<PlayerCamera>>,
+) {
+    for entity in cameras.iter() {
+        let mut controller = CameraController::default();
+        if let Some(speeds) = settings.controller {
+            speeds.apply(&mut controller);
+        }
+        com.entity(entity).insert(controller.print_controls());
+    }
+}
+
+// save_settings_on_exit doesn't know about the controller, so its speeds are kept up to date here
+fn track_controller_speeds(
+    mut settings: ResMut<AppSettings>,
+    controllers: Query<&CameraController>,
+) {
+    if let Some(controller) = controllers.iter().next() {
+        let speeds = Some(ControllerSpeeds::from_controller(controller));
+        if settings.controller != speeds {
+            settings.controller = speeds;
+        }
+    }
+}
+
+// The fly controller would fight walk mode, path playback, bookmark transitions and egui
+fn update_fly_controller(
+    mut egui_context: ResMut<EguiContext>,
+    walk: Res<WalkSettings>,
+    path: Res<CameraPathPlayer>,
+    bookmarks: Res<CameraBookmarks>,
+    mut controllers: Query<&mut CameraController>,
+) {
+    let enabled = walk.mode == CameraMode::Fly
+        && !path.playing
+        && !bookmarks.transitioning()
+        && !egui_context.ctx_mut().is_using_pointer();
+    for mut controller in controllers.iter_mut() {
+        if controller.enabled != enabled {
+            controller.enabled = enabled;
+        }
+    }
+}
+
+// bevy_basic_camera's controller on the PlayerCamera. Needs a window for egui
+pub struct FlyCameraPlugin;
+
+impl Plugin for FlyCameraPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(CameraControllerPlugin)
+            .add_system(add_fly_controller)
+            .add_system(track_controller_speeds)
+            .add_system(update_fly_controller);
+    }
+}
@@ -0,0 +1,184 @@
+use bevy::{
+    asset::load_internal_asset,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{render_resource::ShaderType, texture::TextureFormatPixelInfo},
+};
+use bevy_egui::egui;
+
+use crate::custom_material::CustomMaterial;
+use crate::emissive_material::EmissiveMaterial;
+use crate::fog_standard_material::FogStandardMaterial;
+use crate::system_timings::timed;
+
+pub const FOG_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x407d1a30fe70430f);
+
+// Shared by every material that receives fog, see assets/shaders/fog.wgsl
+#[derive(ShaderType, Debug, Clone, Copy, PartialEq)]
+pub struct FogUniform {
+    pub color: Color,
+    pub density: f32,
+    pub height_falloff: f32,
+    pub base_height: f32,
+    pub max_opacity: f32,
+}
+
+impl Default for FogUniform {
+    fn default() -> Self {
+        FogUniform {
+            color: Color::WHITE,
+            density: 0.0,
+            height_falloff: 0.0,
+            base_height: 0.0,
+            max_opacity: 0.0,
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct FogSettings {
+    pub color: Color,
+    // If set, color is replaced with the average color of this image once it's loaded
+    pub sky: Option<Handle<Image>>,
+    pub density: f32,
+    pub height_falloff: f32,
+    pub base_height: f32,
+    pub max_opacity: f32,
+}
+
+impl Default for FogSettings {
+    fn default() -> Self {
+        FogSettings {
+            color: Color::rgb(0.5, 0.5, 0.5),
+            sky: None,
+            density: 0.0,
+            height_falloff: 0.1,
+            base_height: 0.0,
+            max_opacity: 1.0,
+        }
+    }
+}
+
+impl FogSettings {
+    pub fn uniform(&self) -> FogUniform {
+        FogUniform {
+            color: self.color,
+            density: self.density,
+            height_falloff: self.height_falloff,
+            base_height: self.base_height,
+            max_opacity: self.max_opacity,
+        }
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        let [r, g, b, _] = self.color.as_linear_rgba_f32();
+        let mut color = [r, g, b];
+        ui.horizontal(|ui| {
+            if ui.color_edit_button_rgb(&mut color).changed() {
+                self.color = Color::rgb_linear(color[0], color[1], color[2]);
+            }
+            ui.label("color");
+        });
+        ui.add(
+            egui::Slider::new(&mut self.density, 0.0..=1.0)
+                .logarithmic(true)
+                .text("density"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.height_falloff, 0.0..=2.0)
+                .logarithmic(true)
+                .text("height_falloff"),
+        );
+        ui.add(egui::Slider::new(&mut self.base_height, -20.0..=50.0).text("base_height"));
+        ui.add(egui::Slider::new(&mut self.max_opacity, 0.0..=1.0).text("max_opacity"));
+    }
+}
+
+pub trait FogMaterial: Material {
+    fn fog(&self) -> &FogUniform;
+    fn fog_mut(&mut self) -> &mut FogUniform;
+}
+
+impl FogMaterial for CustomMaterial {
+    fn fog(&self) -> &FogUniform {
+        &self.fog
+    }
+    fn fog_mut(&mut self) -> &mut FogUniform {
+        &mut self.fog
+    }
+}
+
+impl FogMaterial for EmissiveMaterial {
+    fn fog(&self) -> &FogUniform {
+        &self.fog
+    }
+    fn fog_mut(&mut self) -> &mut FogUniform {
+        &mut self.fog
+    }
+}
+
+impl FogMaterial for FogStandardMaterial {
+    fn fog(&self) -> &FogUniform {
+        &self.fog
+    }
+    fn fog_mut(&mut self) -> &mut FogUniform {
+        &mut self.fog
+    }
+}
+
+// Average of a loaded 8 bit image, computed in linear space
+fn average_color(image: &Image) -> Option<Color> {
+    let pixel_size = image.texture_descriptor.format.pixel_size();
+    if pixel_size != 4 || image.data.is_empty() {
+        return None;
+    }
+    // No need to look at every pixel of a large skybox
+    let step = (image.data.len() / pixel_size / 4096).max(1);
+    let mut sum = Vec3::ZERO;
+    let mut count = 0.0;
+    for px in image.data.chunks_exact(pixel_size).step_by(step) {
+        let linear = Color::rgb_u8(px[0], px[1], px[2]).as_linear_rgba_f32();
+        sum += Vec3::new(linear[0], linear[1], linear[2]);
+        count += 1.0;
+    }
+    let avg = sum / count;
+    Some(Color::rgb_linear(avg.x, avg.y, avg.z))
+}
+
+pub fn sky_fog_color(mut fog: ResMut<FogSettings>, images: Res<Assets<Image>>) {
+    if let Some(sky) = fog.sky.clone() {
+        if let Some(color) = images.get(&sky).and_then(average_color) {
+            fog.color = color;
+            fog.sky = None;
+        }
+    }
+}
+
+pub fn sync_fog<M: FogMaterial>(fog: Res<FogSettings>, mut materials: ResMut<Assets<M>>) {
+    let uniform = fog.uniform();
+    // Only touch the materials if something is stale, so they aren't re-prepared every frame
+    if materials.iter().any(|(_, mat)| *mat.fog() != uniform) {
+        for (_, mat) in materials.iter_mut() {
+            *mat.fog_mut() = uniform;
+        }
+    }
+}
+
+pub struct FogPlugin;
+
+impl Plugin for FogPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            FOG_SHADER_HANDLE,
+            "../assets/shaders/fog.wgsl",
+            Shader::from_wgsl
+        );
+        app.init_resource::<FogSettings>()
+            .add_system(sky_fog_color)
+            .add_system(timed(sync_fog::<CustomMaterial>).after(sky_fog_color))
+            .add_system(timed(sync_fog::<EmissiveMaterial>).after(sky_fog_color))
+            .add_system(timed(sync_fog::<FogStandardMaterial>).after(sky_fog_color));
+    }
+}
@@ -0,0 +1,42 @@
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::render_resource::{AsBindGroup, ShaderRef},
+};
+
+use crate::fog::FogUniform;
+
+// A lit StandardMaterial subset that also receives the level fog
+#[derive(AsBindGroup, Debug, Clone, TypeUuid)]
+#[uuid = "824393dd-55ec-44cf-b1b7-9e7ae66757b7"]
+pub struct FogStandardMaterial {
+    #[uniform(0)]
+    pub base_color: Color,
+    #[uniform(0)]
+    pub perceptual_roughness: f32,
+    #[uniform(0)]
+    pub metallic: f32,
+    #[uniform(0)]
+    pub reflectance: f32,
+    #[uniform(1)]
+    pub fog: FogUniform,
+}
+
+impl Default for FogStandardMaterial {
+    fn default() -> Self {
+        // Same defaults as StandardMaterial
+        FogStandardMaterial {
+            base_color: Color::WHITE,
+            perceptual_roughness: 0.089,
+            metallic: 0.01,
+            reflectance: 0.5,
+            fog: FogUniform::default(),
+        }
+    }
+}
+
+impl Material for FogStandardMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/fog_standard_material.wgsl".into()
+    }
+}
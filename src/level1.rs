@@ -1,7 +1,14 @@
 use bevy::prelude::*;
 
-use crate::custom_material::{load_mark, CustomMaterial, MaterialProperties, MaterialSetProp};
+use crate::baked_lights::{BakedLight, BakedLightData};
+use crate::custom_material::{
+    load_mark, CustomMaterial, DebugView, MaterialProperties, MaterialSetProp, ShadowMasks,
+};
+use crate::data_texture::DataTexture;
 use crate::emissive_material::EmissiveMaterial;
+use crate::fog::{FogSettings, FogUniform};
+use crate::level_camera::LevelCamera;
+use crate::occluders::OccluderData;
 use crate::LevelItem;
 
 pub fn setup_room(
@@ -9,6 +16,7 @@ pub fn setup_room(
     custom_materials: &mut Assets<CustomMaterial>,
     emissive_materials: &mut Assets<EmissiveMaterial>,
     ass: &Res<AssetServer>,
+    shadow_masks: &ShadowMasks,
 ) {
     //Building Objects
     let building_objects = ass.load("models/scene1/building.glb#Mesh0/Primitive0");
@@ -62,19 +70,17 @@ pub fn setup_room(
             brightness: 40.0,
             blend: 1.0,
         },
-        mist: MaterialSetProp {
-            scale: 0.032,
-            contrast: 1.0,
-            brightness: 1.0,
-            blend: 0.567,
-        },
-        directional_light_blend: 0.6,
+        directional_light_blend: 0.115,
+        dynamic_light_blend: 0.5,
+        shadow_distance: 20.0,
+        shadow_fade: 5.0,
     };
 
     let mut material = CustomMaterial {
         material_properties,
-        lightmap: Some(ass.load("textures/scene1/objects_lightmap.jpg")),
-        lightmap_path: String::from("textures/scene1/objects_lightmap.jpg"),
+        // Set per mesh with set_lightmap, along with its shadow mask
+        lightmap: None,
+        lightmap_path: String::new(),
         base: Some(load_mark(com, ass, "textures/concrete.jpg")),
         base_path: String::from("textures/concrete.jpg"),
         vary: Some(load_mark(com, ass, "textures/detail.jpg")),
@@ -83,7 +89,14 @@ pub fn setup_room(
         reflection_path: String::from("textures/scene1/reflection.jpg"),
         walls: Some(load_mark(com, ass, "textures/concrete3.jpg")),
         walls_path: String::from("textures/concrete3.jpg"),
+        fog: FogUniform::default(),
+        occluders: OccluderData::texture(),
+        shadow_mask: None,
+        shadow_mask_path: String::new(),
+        baked_lights: BakedLightData::texture(),
+        debug_view: DebugView::None,
     };
+    material.set_lightmap(ass, "textures/scene1/objects_lightmap.jpg", shadow_masks);
 
     com.spawn(MaterialMeshBundle {
         mesh: building_objects,
@@ -96,8 +109,7 @@ pub fn setup_room(
     //Building Main
     let building_main = ass.load("models/scene1/building.glb#Mesh1/Primitive0");
 
-    material.lightmap = Some(ass.load("textures/scene1/main_lightmap.jpg"));
-    material.lightmap_path = String::from("textures/scene1/main_lightmap.jpg");
+    material.set_lightmap(ass, "textures/scene1/main_lightmap.jpg", shadow_masks);
 
     com.spawn(MaterialMeshBundle {
         mesh: building_main,
@@ -115,12 +127,26 @@ pub fn setup_room(
         transform: Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::new(10.0, 10.0, 10.0)),
         material: emissive_materials.add(EmissiveMaterial {
             emissive: Color::WHITE,
-            emissive_texture: Some(skybox_texture),
+            emissive_texture: Some(skybox_texture.clone()),
+            fog: FogUniform::default(),
         }),
         ..Default::default()
     })
     .insert(LevelItem);
 
+    //Camera spawn
+    com.insert_resource(LevelCamera::new(Vec3::new(-2.0, 2.5, 5.0), Vec3::ZERO));
+
+    //Fog, colored from the sky
+    com.insert_resource(FogSettings {
+        sky: Some(skybox_texture),
+        density: 0.02,
+        height_falloff: 0.15,
+        base_height: 0.0,
+        max_opacity: 0.6,
+        ..default()
+    });
+
     //Bevy Sun
     let size: f32 = 50.0;
     com.spawn(DirectionalLightBundle {
@@ -135,6 +161,7 @@ pub fn setup_room(
                 far: size * 1.0,
                 ..Default::default()
             },
+            color: Color::rgb(1.0, 0.9, 0.5),
             illuminance: 100000.0,
             shadows_enabled: true,
             ..Default::default()
@@ -166,7 +193,8 @@ pub fn setup_room(
         },
         ..Default::default()
     })
-    .insert(LevelItem);
+    .insert(LevelItem)
+    .insert(BakedLight);
 
     // Only doing a couple light positions because Bevy complains:
     // WARN bevy_pbr::render::light: Cluster light index lists is full!
@@ -196,6 +224,7 @@ pub fn setup_room(
             },
             ..Default::default()
         })
-        .insert(LevelItem);
+        .insert(LevelItem)
+        .insert(BakedLight);
     }
 }
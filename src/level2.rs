@@ -1,7 +1,14 @@
 use bevy::prelude::*;
 
-use crate::custom_material::{load_mark, CustomMaterial, MaterialProperties, MaterialSetProp};
+use crate::baked_lights::{BakedLight, BakedLightData};
+use crate::custom_material::{
+    load_mark, CustomMaterial, DebugView, MaterialProperties, MaterialSetProp, ShadowMasks,
+};
+use crate::data_texture::DataTexture;
 use crate::emissive_material::EmissiveMaterial;
+use crate::fog::{FogSettings, FogUniform};
+use crate::level_camera::LevelCamera;
+use crate::occluders::OccluderData;
 use crate::LevelItem;
 
 pub fn setup_room(
@@ -9,6 +16,7 @@ pub fn setup_room(
     custom_materials: &mut Assets<CustomMaterial>,
     emissive_materials: &mut Assets<EmissiveMaterial>,
     ass: &Res<AssetServer>,
+    shadow_masks: &ShadowMasks,
 ) {
     //Building Objects
     let material_properties = MaterialProperties {
@@ -60,19 +68,17 @@ pub fn setup_room(
             brightness: 40.0,
             blend: 1.0,
         },
-        mist: MaterialSetProp {
-            scale: 0.021,
-            contrast: 1.7,
-            brightness: 17.0,
-            blend: 0.78,
-        },
-        directional_light_blend: 0.6,
+        directional_light_blend: 0.115,
+        dynamic_light_blend: 0.5,
+        shadow_distance: 20.0,
+        shadow_fade: 5.0,
     };
 
     let mut material = CustomMaterial {
         material_properties,
-        lightmap: Some(ass.load("textures/scene2/objects_lightmap.jpg")),
-        lightmap_path: String::from("textures/scene2/objects_lightmap.jpg"),
+        // Set per mesh with set_lightmap, along with its shadow mask
+        lightmap: None,
+        lightmap_path: String::new(),
         base: Some(load_mark(com, ass, "textures/concrete.jpg")),
         base_path: String::from("textures/concrete.jpg"),
         vary: Some(load_mark(com, ass, "textures/detail.jpg")),
@@ -81,7 +87,14 @@ pub fn setup_room(
         reflection_path: String::from("textures/scene1/reflection.jpg"),
         walls: Some(load_mark(com, ass, "textures/concrete3.jpg")),
         walls_path: String::from("textures/concrete3.jpg"),
+        fog: FogUniform::default(),
+        occluders: OccluderData::texture(),
+        shadow_mask: None,
+        shadow_mask_path: String::new(),
+        baked_lights: BakedLightData::texture(),
+        debug_view: DebugView::None,
     };
+    material.set_lightmap(ass, "textures/scene2/objects_lightmap.jpg", shadow_masks);
 
     let material_handle = custom_materials.add(material.clone());
 
@@ -118,8 +131,7 @@ pub fn setup_room(
     //Building Main
     let building_main = ass.load("models/scene2/building.glb#Mesh1/Primitive0");
 
-    material.lightmap = Some(ass.load("textures/scene2/walls_lightmap.jpg"));
-    material.lightmap_path = String::from("textures/scene2/walls_lightmap.jpg");
+    material.set_lightmap(ass, "textures/scene2/walls_lightmap.jpg", shadow_masks);
 
     com.spawn(MaterialMeshBundle {
         mesh: building_main,
@@ -137,44 +149,63 @@ pub fn setup_room(
         transform: Transform::from_xyz(0.0, 0.0, 0.0).with_scale(Vec3::new(10.0, 10.0, 10.0)),
         material: emissive_materials.add(EmissiveMaterial {
             emissive: Color::WHITE,
-            emissive_texture: Some(skybox_texture),
+            emissive_texture: Some(skybox_texture.clone()),
+            fog: FogUniform::default(),
         }),
         ..Default::default()
     })
     .insert(LevelItem);
 
-    //let blender_sun_elev = 24.4f32;
-    //let blender_sun_rot = 248.0f32;
-    ////Bevy Sun
-    //let size: f32 = 250.0;
-    //com.spawn_bundle(DirectionalLightBundle {
-    //    directional_light: DirectionalLight {
-    //        // Configure the projection to better fit the scene
-    //        shadow_projection: OrthographicProjection {
-    //            left: -size * 4.0,
-    //            right: size * 2.0,
-    //            bottom: -size * 2.0,
-    //            top: size * 1.0,
-    //            near: -size * 2.0,
-    //            far: size * 1.0,
-    //            ..Default::default()
-    //        },
-    //        illuminance: 100000.0,
-    //        shadows_enabled: true,
-    //        ..Default::default()
-    //    },
-    //    transform: Transform {
-    //        translation: Vec3::new(0.0, 0.0, 0.0),
-    //        rotation: Quat::from_euler(
-    //            EulerRot::XYZ,
-    //            (-blender_sun_elev).to_radians(),
-    //            -(blender_sun_rot - 180.0f32).to_radians(),
-    //            0.0,
-    //        ),
-    //        ..Default::default()
-    //    },
-    //    ..Default::default()
-    //});
+    //Camera spawn
+    com.insert_resource(
+        LevelCamera::new(Vec3::new(-12.0, 4.0, 18.0), Vec3::new(0.0, 2.0, 0.0))
+            .with_clip(0.1, 2000.0),
+    );
+
+    //Fog, colored from the sky
+    com.insert_resource(FogSettings {
+        sky: Some(skybox_texture),
+        density: 0.008,
+        height_falloff: 0.05,
+        base_height: 0.0,
+        max_opacity: 0.8,
+        ..default()
+    });
+
+    //Bevy Sun, matching the sun the lightmaps were baked with in blender
+    let blender_sun_elev = 24.4f32;
+    let blender_sun_rot = 248.0f32;
+    let size: f32 = 250.0;
+    com.spawn(DirectionalLightBundle {
+        directional_light: DirectionalLight {
+            // Configure the projection to better fit the scene
+            shadow_projection: OrthographicProjection {
+                left: -size * 4.0,
+                right: size * 2.0,
+                bottom: -size * 2.0,
+                top: size * 1.0,
+                near: -size * 2.0,
+                far: size * 1.0,
+                ..Default::default()
+            },
+            color: Color::rgb(1.0, 0.9, 0.5),
+            illuminance: 100000.0,
+            shadows_enabled: true,
+            ..Default::default()
+        },
+        transform: Transform {
+            translation: Vec3::new(0.0, 0.0, 0.0),
+            rotation: Quat::from_euler(
+                EulerRot::XYZ,
+                (-blender_sun_elev).to_radians(),
+                -(blender_sun_rot - 180.0f32).to_radians(),
+                0.0,
+            ),
+            ..Default::default()
+        },
+        ..Default::default()
+    })
+    .insert(LevelItem);
 
     //Sky Light for PBR
     com.spawn(PointLightBundle {
@@ -189,5 +220,6 @@ pub fn setup_room(
         },
         ..Default::default()
     })
-    .insert(LevelItem);
+    .insert(LevelItem)
+    .insert(BakedLight);
 }
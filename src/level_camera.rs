@@ -0,0 +1,99 @@
+use bevy::prelude::*;
+use bevy_egui::egui;
+
+use crate::walk_camera::Walker;
+use crate::PlayerCamera;
+
+// Where the camera starts in a level and its projection, each level's setup_room inserts one
+#[derive(Resource, Debug, Clone, Copy)]
+pub struct LevelCamera {
+    pub position: Vec3,
+    pub look_at: Vec3,
+    // Vertical field of view in degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+    // Move the camera to the spawn point the next time apply_level_camera runs
+    respawn: bool,
+}
+
+impl Default for LevelCamera {
+    fn default() -> Self {
+        LevelCamera::new(Vec3::new(-2.0, 2.5, 5.0), Vec3::ZERO)
+    }
+}
+
+impl LevelCamera {
+    pub fn new(position: Vec3, look_at: Vec3) -> Self {
+        LevelCamera {
+            position,
+            look_at,
+            fov: 45.0,
+            near: 0.1,
+            far: 1000.0,
+            respawn: true,
+        }
+    }
+
+    pub fn with_fov(mut self, fov: f32) -> Self {
+        self.fov = fov;
+        self
+    }
+
+    pub fn with_clip(mut self, near: f32, far: f32) -> Self {
+        self.near = near;
+        self.far = far;
+        self
+    }
+
+    pub fn transform(&self) -> Transform {
+        Transform::from_translation(self.position).looking_at(self.look_at, Vec3::Y)
+    }
+
+    pub fn respawn(&mut self) {
+        self.respawn = true;
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.fov, 10.0..=120.0).text("fov"));
+        ui.add(
+            egui::Slider::new(&mut self.near, 0.01..=10.0)
+                .logarithmic(true)
+                .text("near"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.far, 10.0..=10000.0)
+                .logarithmic(true)
+                .text("far"),
+        );
+        if ui.button("Go To Spawn").clicked() {
+            self.respawn();
+        }
+    }
+}
+
+pub fn apply_level_camera(
+    mut level_camera: ResMut<LevelCamera>,
+    mut cameras: Query<(&mut Transform, &mut Projection, Option<&mut Walker>), With<PlayerCamera>>,
+) {
+    if !level_camera.is_changed() {
+        return;
+    }
+    let (mut transform, mut projection, walker) = match cameras.get_single_mut() {
+        Ok(camera) => camera,
+        Err(_) => return,
+    };
+    if let Projection::Perspective(perspective) = &mut *projection {
+        perspective.fov = level_camera.fov.to_radians();
+        perspective.near = level_camera.near;
+        perspective.far = level_camera.far.max(level_camera.near + 0.01);
+    }
+    if level_camera.respawn {
+        // Don't trigger another change just for clearing the flag
+        level_camera.bypass_change_detection().respawn = false;
+        *transform = level_camera.transform();
+        if let Some(mut walker) = walker {
+            walker.reset();
+        }
+    }
+}
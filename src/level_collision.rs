@@ -0,0 +1,395 @@
+use bevy::{
+    prelude::*,
+    render::mesh::{PrimitiveTopology, VertexAttributeValues},
+};
+
+use crate::custom_material::CustomMaterial;
+use crate::LevelItem;
+
+// Triangles per BVH leaf
+const LEAF_SIZE: usize = 4;
+const STACK_SIZE: usize = 64;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Triangle {
+    pub a: Vec3,
+    pub b: Vec3,
+    pub c: Vec3,
+}
+
+impl Triangle {
+    fn min(&self) -> Vec3 {
+        self.a.min(self.b).min(self.c)
+    }
+
+    fn max(&self) -> Vec3 {
+        self.a.max(self.b).max(self.c)
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.a + self.b + self.c) / 3.0
+    }
+
+    // Real-Time Collision Detection, Christer Ericson, 5.1.5
+    pub fn closest_point(&self, p: Vec3) -> Vec3 {
+        let (a, b, c) = (self.a, self.b, self.c);
+        let ab = b - a;
+        let ac = c - a;
+        let ap = p - a;
+        let d1 = ab.dot(ap);
+        let d2 = ac.dot(ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return a;
+        }
+        let bp = p - b;
+        let d3 = ab.dot(bp);
+        let d4 = ac.dot(bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return b;
+        }
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            return a + ab * (d1 / (d1 - d3));
+        }
+        let cp = p - c;
+        let d5 = ab.dot(cp);
+        let d6 = ac.dot(cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return c;
+        }
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            return a + ac * (d2 / (d2 - d6));
+        }
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+        }
+        let denom = 1.0 / (va + vb + vc);
+        a + ab * (vb * denom) + ac * (vc * denom)
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+struct BvhNode {
+    min: Vec3,
+    max: Vec3,
+    // Leaves index into triangles, inner nodes have their left child at first and the right at first + 1
+    first: u32,
+    count: u32,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Contact {
+    // Points from the surface towards the sphere center
+    pub normal: Vec3,
+    pub depth: f32,
+}
+
+// Static bounding volume hierarchy over the level triangles
+#[derive(Default)]
+pub struct TriangleBvh {
+    triangles: Vec<Triangle>,
+    nodes: Vec<BvhNode>,
+}
+
+impl TriangleBvh {
+    pub fn new(mut triangles: Vec<Triangle>) -> Self {
+        let mut nodes = Vec::new();
+        if !triangles.is_empty() {
+            let len = triangles.len();
+            nodes.push(BvhNode {
+                min: Vec3::ZERO,
+                max: Vec3::ZERO,
+                first: 0,
+                count: len as u32,
+            });
+            Self::split(&mut nodes, &mut triangles, 0, 0, len);
+        }
+        TriangleBvh { triangles, nodes }
+    }
+
+    fn split(
+        nodes: &mut Vec<BvhNode>,
+        triangles: &mut [Triangle],
+        index: usize,
+        start: usize,
+        end: usize,
+    ) {
+        let tris = &mut triangles[start..end];
+        let mut min = Vec3::splat(f32::MAX);
+        let mut max = Vec3::splat(f32::MIN);
+        for tri in tris.iter() {
+            min = min.min(tri.min());
+            max = max.max(tri.max());
+        }
+        nodes[index] = BvhNode {
+            min,
+            max,
+            first: start as u32,
+            count: (end - start) as u32,
+        };
+        if tris.len() <= LEAF_SIZE {
+            return;
+        }
+
+        // Median split along the longest axis
+        let extent = max - min;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        tris.sort_unstable_by(|a, b| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+        let mid = start + tris.len() / 2;
+
+        let left = nodes.len();
+        let placeholder = nodes[index];
+        nodes.push(placeholder);
+        nodes.push(placeholder);
+        nodes[index].first = left as u32;
+        nodes[index].count = 0;
+        Self::split(nodes, triangles, left, start, mid);
+        Self::split(nodes, triangles, left + 1, mid, end);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.nodes.is_empty()
+    }
+
+    // Calls f with every contact between the sphere and the level
+    pub fn sphere_contacts(&self, center: Vec3, radius: f32, mut f: impl FnMut(Contact)) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        let sphere_min = center - radius;
+        let sphere_max = center + radius;
+        let mut stack = [0u32; STACK_SIZE];
+        let mut len = 1;
+        while len > 0 {
+            len -= 1;
+            let node = &self.nodes[stack[len] as usize];
+            if (sphere_max.cmplt(node.min) | sphere_min.cmpgt(node.max)).any() {
+                continue;
+            }
+            if node.count == 0 {
+                if len + 2 > STACK_SIZE {
+                    continue;
+                }
+                stack[len] = node.first;
+                stack[len + 1] = node.first + 1;
+                len += 2;
+                continue;
+            }
+            let first = node.first as usize;
+            for tri in &self.triangles[first..first + node.count as usize] {
+                let offset = center - tri.closest_point(center);
+                let distance_squared = offset.length_squared();
+                if distance_squared < radius * radius && distance_squared > 0.0 {
+                    let distance = distance_squared.sqrt();
+                    f(Contact {
+                        normal: offset / distance,
+                        depth: radius - distance,
+                    });
+                }
+            }
+        }
+    }
+}
+
+// Collision geometry for the currently loaded level, built from the CustomMaterial meshes
+#[derive(Resource, Default)]
+pub struct LevelCollider {
+    pub bvh: TriangleBvh,
+    sources: Vec<HandleId>,
+}
+
+fn mesh_triangles(mesh: &Mesh, transform: &GlobalTransform, triangles: &mut Vec<Triangle>) {
+    if mesh.primitive_topology() != PrimitiveTopology::TriangleList {
+        return;
+    }
+    let positions = match mesh.attribute(Mesh::ATTRIBUTE_POSITION) {
+        Some(VertexAttributeValues::Float32x3(positions)) => positions,
+        _ => return,
+    };
+    let matrix = transform.compute_matrix();
+    let vertex = |i: usize| matrix.transform_point3(Vec3::from(positions[i]));
+    let indices: Vec<usize> = match mesh.indices() {
+        Some(indices) => indices.iter().collect(),
+        None => (0..positions.len()).collect(),
+    };
+    for tri in indices.chunks_exact(3) {
+        triangles.push(Triangle {
+            a: vertex(tri[0]),
+            b: vertex(tri[1]),
+            c: vertex(tri[2]),
+        });
+    }
+}
+
+// Rebuilds the collider whenever the set of level meshes changes, once they've all loaded
+pub fn build_level_collider(
+    mut collider: ResMut<LevelCollider>,
+    meshes: Res<Assets<Mesh>>,
+    level_meshes: Query<
+        (&Handle<Mesh>, &GlobalTransform),
+        (With<LevelItem>, With<Handle<CustomMaterial>>),
+    >,
+) {
+    let sources_match = collider.sources.len() == level_meshes.iter().count()
+        && level_meshes
+            .iter()
+            .zip(collider.sources.iter())
+            .all(|((handle, _), id)| handle.id() == *id);
+    if sources_match {
+        return;
+    }
+    if level_meshes
+        .iter()
+        .any(|(handle, _)| meshes.get(handle).is_none())
+    {
+        return;
+    }
+
+    let mut triangles = Vec::new();
+    let mut sources = Vec::new();
+    for (handle, transform) in level_meshes.iter() {
+        if let Some(mesh) = meshes.get(handle) {
+            mesh_triangles(mesh, transform, &mut triangles);
+        }
+        sources.push(handle.id());
+    }
+    collider.bvh = TriangleBvh::new(triangles);
+    collider.sources = sources;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle() -> Triangle {
+        Triangle {
+            a: Vec3::ZERO,
+            b: Vec3::X,
+            c: Vec3::Z,
+        }
+    }
+
+    // A size by size grid of unit quads on y = 0
+    fn floor(size: usize) -> Vec<Triangle> {
+        let mut triangles = Vec::new();
+        for x in 0..size {
+            for z in 0..size {
+                let corner = Vec3::new(x as f32, 0.0, z as f32);
+                triangles.push(Triangle {
+                    a: corner,
+                    b: corner + Vec3::X,
+                    c: corner + Vec3::Z,
+                });
+                triangles.push(Triangle {
+                    a: corner + Vec3::X,
+                    b: corner + Vec3::X + Vec3::Z,
+                    c: corner + Vec3::Z,
+                });
+            }
+        }
+        triangles
+    }
+
+    fn contacts(bvh: &TriangleBvh, center: Vec3, radius: f32) -> Vec<Contact> {
+        let mut contacts = Vec::new();
+        bvh.sphere_contacts(center, radius, |contact| contacts.push(contact));
+        contacts
+    }
+
+    #[test]
+    fn closest_point_regions() {
+        let tri = triangle();
+        // Face
+        assert_eq!(
+            tri.closest_point(Vec3::new(0.25, 1.0, 0.25)),
+            Vec3::new(0.25, 0.0, 0.25)
+        );
+        // Vertices
+        assert_eq!(tri.closest_point(Vec3::new(-1.0, 0.0, -1.0)), tri.a);
+        assert_eq!(tri.closest_point(Vec3::new(2.0, 1.0, -0.5)), tri.b);
+        assert_eq!(tri.closest_point(Vec3::new(-0.5, -1.0, 2.0)), tri.c);
+        // Edges
+        assert_eq!(
+            tri.closest_point(Vec3::new(0.5, 0.0, -1.0)),
+            Vec3::new(0.5, 0.0, 0.0)
+        );
+        assert_eq!(
+            tri.closest_point(Vec3::new(-1.0, 0.0, 0.5)),
+            Vec3::new(0.0, 0.0, 0.5)
+        );
+        let hypotenuse = tri.closest_point(Vec3::new(1.0, 0.0, 1.0));
+        assert!(hypotenuse.distance(Vec3::new(0.5, 0.0, 0.5)) < 1e-6);
+    }
+
+    #[test]
+    fn closest_point_is_closest() {
+        let tri = Triangle {
+            a: Vec3::new(-1.0, 0.5, 0.0),
+            b: Vec3::new(2.0, -0.5, 1.0),
+            c: Vec3::new(0.5, 1.5, -2.0),
+        };
+        for i in 0..100 {
+            let i = i as f32;
+            let p = Vec3::new((i * 1.7).sin(), (i * 0.9).cos(), (i * 2.3).sin()) * 3.0;
+            let closest = tri.closest_point(p).distance(p);
+            // No point on the triangle, sampled by barycentric coordinates, is closer
+            for u in 0..=10 {
+                for v in 0..=(10 - u) {
+                    let (u, v) = (u as f32 / 10.0, v as f32 / 10.0);
+                    let sample = tri.a + (tri.b - tri.a) * u + (tri.c - tri.a) * v;
+                    assert!(closest <= sample.distance(p) + 1e-5);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn empty_bvh_has_no_contacts() {
+        let bvh = TriangleBvh::new(Vec::new());
+        assert!(bvh.is_empty());
+        assert!(contacts(&bvh, Vec3::ZERO, 1.0).is_empty());
+    }
+
+    #[test]
+    fn sphere_resting_on_floor() {
+        let bvh = TriangleBvh::new(floor(16));
+        // Far enough from the edges that only the triangle below is touched
+        let found = contacts(&bvh, Vec3::new(4.25, 0.48, 7.25), 0.5);
+        assert_eq!(found.len(), 1);
+        assert!(found[0].normal.distance(Vec3::Y) < 1e-5);
+        assert!((found[0].depth - 0.02).abs() < 1e-5);
+        assert!(contacts(&bvh, Vec3::new(4.3, 0.6, 7.6), 0.5).is_empty());
+        assert!(contacts(&bvh, Vec3::new(40.0, 0.0, 7.6), 0.5).is_empty());
+    }
+
+    #[test]
+    fn bvh_matches_brute_force() {
+        let triangles = floor(16);
+        let bvh = TriangleBvh::new(triangles.clone());
+        for i in 0..50 {
+            let i = i as f32;
+            let center = Vec3::new(
+                8.0 + (i * 1.3).sin() * 9.0,
+                (i * 0.7).cos(),
+                8.0 + i.cos() * 9.0,
+            );
+            let radius = 0.3 + (i * 0.31).fract();
+            let expected = triangles
+                .iter()
+                .filter(|tri| {
+                    let distance_squared = tri.closest_point(center).distance_squared(center);
+                    distance_squared < radius * radius && distance_squared > 0.0
+                })
+                .count();
+            assert_eq!(contacts(&bvh, center, radius).len(), expected);
+        }
+    }
+}
@@ -0,0 +1,224 @@
+use bevy::{core_pipeline::tonemapping::Tonemapping, prelude::*};
+
+pub mod app_settings;
+pub mod app_state;
+pub mod auto_exposure;
+pub mod baked_lights;
+#[cfg(feature = "planets")]
+pub mod barnes_hut;
+pub mod benchmark;
+pub mod camera_bookmarks;
+pub mod camera_path;
+pub mod custom_material;
+pub mod data_texture;
+#[cfg(feature = "editor-ui")]
+pub mod editor_ui;
+pub mod emissive_material;
+#[cfg(feature = "fly-camera")]
+pub mod fly_camera;
+pub mod fog;
+pub mod fog_standard_material;
+pub mod level1;
+pub mod level2;
+pub mod level_camera;
+pub mod level_collision;
+pub mod occluders;
+#[cfg(feature = "planets")]
+pub mod planet_collisions;
+#[cfg(feature = "planets")]
+pub mod planet_debug;
+#[cfg(feature = "planets")]
+pub mod planet_history;
+#[cfg(feature = "planets")]
+pub mod planet_picking;
+#[cfg(feature = "planets")]
+pub mod planets;
+pub mod ron_file;
+pub mod system_timings;
+pub mod tonemapping;
+pub mod walk_camera;
+use app_settings::AppSettings;
+use app_state::{load_level_events, state_menus, AppStatePlugin, LoadLevel};
+use auto_exposure::AutoExposurePlugin;
+use baked_lights::BakedLightsPlugin;
+use bevy_egui::EguiPlugin;
+use camera_bookmarks::{camera_bookmarks_system, level_camera_bookmarks, CameraBookmarks};
+use camera_path::{camera_path_system, load_level_camera_path, CameraPathPlayer};
+use custom_material::{set_texture_settings, CustomMaterial, ShadowMasks};
+#[cfg(feature = "editor-ui")]
+use editor_ui::EditorUiPlugin;
+use emissive_material::EmissiveMaterial;
+use fog::FogPlugin;
+use fog_standard_material::FogStandardMaterial;
+use level_camera::{apply_level_camera, LevelCamera};
+use level_collision::{build_level_collider, LevelCollider};
+use occluders::OccludersPlugin;
+#[cfg(feature = "planets")]
+use planet_debug::PlanetDebugPlugin;
+#[cfg(feature = "planets")]
+use planets::PlanetsPlugin;
+use system_timings::timed;
+use tonemapping::{CameraExposure, TonemappingPlugin};
+use walk_camera::{walk_camera, WalkSettings, Walker};
+
+#[derive(Component)]
+pub struct LevelItem;
+
+// The camera that's walked, flown, moved along paths and placed by the level
+#[derive(Component)]
+pub struct PlayerCamera;
+
+// Which level is loaded, per level data like camera paths is stored under its name
+#[derive(Resource, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CurrentLevel {
+    #[default]
+    None,
+    Level1,
+    Level2,
+}
+
+impl CurrentLevel {
+    pub const LEVELS: [CurrentLevel; 2] = [CurrentLevel::Level1, CurrentLevel::Level2];
+
+    pub fn name(&self) -> Option<&'static str> {
+        match self {
+            CurrentLevel::None => None,
+            CurrentLevel::Level1 => Some("level1"),
+            CurrentLevel::Level2 => Some("level2"),
+        }
+    }
+
+    pub fn label(&self) -> &'static str {
+        match self {
+            CurrentLevel::None => "None",
+            CurrentLevel::Level1 => "Level 1",
+            CurrentLevel::Level2 => "Level 2",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        CurrentLevel::LEVELS
+            .into_iter()
+            .find(|level| level.name() == Some(name))
+    }
+
+    // Spawns the level and inserts its settings, the previous level has to be despawned first
+    pub fn setup(
+        &self,
+        com: &mut Commands,
+        custom_materials: &mut Assets<CustomMaterial>,
+        emissive_materials: &mut Assets<EmissiveMaterial>,
+        asset_server: &Res<AssetServer>,
+    ) {
+        let shadow_masks = self.name().map(ShadowMasks::load).unwrap_or_default();
+        match self {
+            CurrentLevel::None => (),
+            CurrentLevel::Level1 => level1::setup_room(
+                com,
+                custom_materials,
+                emissive_materials,
+                asset_server,
+                &shadow_masks,
+            ),
+            CurrentLevel::Level2 => level2::setup_room(
+                com,
+                custom_materials,
+                emissive_materials,
+                asset_server,
+                &shadow_masks,
+            ),
+        }
+    }
+}
+
+fn player(mut com: Commands) {
+    // camera, placed by apply_level_camera
+    com.spawn(Camera3dBundle {
+        camera: Camera {
+            hdr: true,
+            ..default()
+        },
+        // Exposed and tone mapped by the ExposureNode, see CameraExposure
+        tonemapping: Tonemapping::Disabled,
+        ..default()
+    })
+    .insert(PlayerCamera)
+    .insert(CameraExposure::default())
+    .insert(Walker::default());
+}
+
+// The baked GI materials, fog, tone mapping, levels and cameras. Needs the DefaultPlugins.
+// AppSettings, WalkSettings and PlanetSpawnConfig are kept if inserted before this is added.
+// The fly camera needs fly_camera::FlyCameraPlugin added as well.
+pub struct BakedGiPlugin {
+    // Loaded on start, CurrentLevel::None starts in the menu
+    pub level: CurrentLevel,
+    // The main menu, pause menu and with the editor-ui feature the Settings window
+    pub ui: bool,
+}
+
+impl Default for BakedGiPlugin {
+    fn default() -> Self {
+        BakedGiPlugin {
+            level: CurrentLevel::Level1,
+            ui: true,
+        }
+    }
+}
+
+impl Plugin for BakedGiPlugin {
+    fn build(&self, app: &mut App) {
+        // walk_camera also uses the egui context, so this is added without the UI too.
+        // Hosts with their own egui UI may have added it already, bevy panics on adding it twice
+        if !app.is_plugin_added::<EguiPlugin>() {
+            app.add_plugin(EguiPlugin);
+        }
+        app.add_plugin(MaterialPlugin::<CustomMaterial>::default())
+            .add_plugin(MaterialPlugin::<EmissiveMaterial>::default())
+            .add_plugin(MaterialPlugin::<FogStandardMaterial>::default())
+            .add_plugin(FogPlugin)
+            .add_plugin(TonemappingPlugin)
+            .add_plugin(AutoExposurePlugin)
+            .add_plugin(OccludersPlugin)
+            .add_plugin(BakedLightsPlugin)
+            .add_plugin(AppStatePlugin)
+            .init_resource::<CurrentLevel>()
+            .init_resource::<AppSettings>()
+            .init_resource::<WalkSettings>()
+            .init_resource::<LevelCamera>()
+            .init_resource::<CameraPathPlayer>()
+            .init_resource::<CameraBookmarks>()
+            .init_resource::<LevelCollider>()
+            .add_system(timed(build_level_collider))
+            .add_system(timed(walk_camera))
+            .add_system(timed(apply_level_camera))
+            .add_system(load_level_camera_path)
+            .add_system(
+                timed(camera_path_system)
+                    .after(load_level_camera_path)
+                    .after(walk_camera),
+            )
+            .add_system(level_camera_bookmarks)
+            .add_system(
+                timed(camera_bookmarks_system)
+                    .after(level_camera_bookmarks)
+                    .after(walk_camera),
+            )
+            .add_startup_system(player)
+            .add_system(set_texture_settings);
+        // Through the loading screen like any other level change
+        let level = self.level;
+        if level != CurrentLevel::None {
+            app.add_startup_system(move |mut load: EventWriter<LoadLevel>| {
+                load.send(LoadLevel(level));
+            });
+        }
+        #[cfg(feature = "planets")]
+        app.add_plugin(PlanetsPlugin).add_plugin(PlanetDebugPlugin);
+        if self.ui {
+            app.add_system(state_menus.after(load_level_events));
+            #[cfg(feature = "editor-ui")]
+            app.add_plugin(EditorUiPlugin);
+        }
+    }
+}
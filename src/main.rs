@@ -1,104 +1,96 @@
-use bevy::{prelude::*, window::CursorGrabMode};
+#[cfg(feature = "fly-camera")]
+use baked_gi::fly_camera::FlyCameraPlugin;
+use baked_gi::{
+    app_settings::{save_settings_on_exit, track_window_size, AppSettings},
+    benchmark::BenchmarkPlugin,
+    BakedGiPlugin,
+};
+use bevy::{
+    app::ScheduleRunnerPlugin, prelude::*, render::settings::WgpuSettings, winit::WinitPlugin,
+};
 
-mod custom_material;
-mod emissive_material;
-mod level1;
-mod level2;
-mod planets;
-use bevy_basic_camera::{CameraController, CameraControllerPlugin};
-use bevy_egui::{egui, EguiContext, EguiPlugin};
-use custom_material::{set_texture_settings, CustomMaterial};
-use emissive_material::EmissiveMaterial;
-use planets::{planitary_physics, spawn_planets};
+mod cli;
+use cli::CliArgs;
 
-#[derive(Component)]
-pub struct LevelItem;
+fn main() {
+    let args = match CliArgs::parse(std::env::args().skip(1)) {
+        Ok(Some(args)) => args,
+        Ok(None) => {
+            println!("{}", cli::USAGE);
+            return;
+        }
+        Err(e) => {
+            eprintln!("{}\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    let benchmark = args.benchmark.clone();
+    let headless = benchmark
+        .as_ref()
+        .map_or(false, |benchmark| benchmark.headless);
+    // Benchmarks start from the defaults and don't touch the settings file, so runs stay comparable
+    let mut app_settings = if benchmark.is_none() {
+        AppSettings::load()
+    } else {
+        AppSettings::default()
+    };
+    args.apply_to(&mut app_settings);
+    // Vsync would cap the measured frame times at the refresh rate, even with --vsync
+    if benchmark.is_some() {
+        app_settings.vsync = false;
+    }
+    let level = benchmark
+        .as_ref()
+        .map_or(app_settings.current_level(), |benchmark| benchmark.level);
 
-#[allow(clippy::too_many_arguments)]
-fn menu_ui(
-    mut com: Commands,
-    mut windows: ResMut<Windows>,
-    mut egui_context: ResMut<EguiContext>,
-    mut custom_materials: ResMut<Assets<CustomMaterial>>,
-    mut emissive_materials: ResMut<Assets<EmissiveMaterial>>,
-    mut material_handles: Query<&mut Handle<CustomMaterial>>,
-    level_items: Query<Entity, With<LevelItem>>,
-    asset_server: Res<AssetServer>,
-    mut controllers: Query<&mut CameraController>,
-) {
-    let window = windows.get_primary_mut().unwrap();
-    let show_ui = window.is_focused() && !(window.cursor_grab_mode() == CursorGrabMode::Locked);
-    if show_ui {
-        egui::Window::new("Settings").show(egui_context.ctx_mut(), |ui| {
-            if ui.button("Load Level 1").clicked() {
-                for entity in level_items.iter() {
-                    com.entity(entity).despawn_recursive();
-                }
-                level1::setup_room(
-                    &mut com,
-                    &mut custom_materials,
-                    &mut emissive_materials,
-                    &asset_server,
-                );
-            }
-            if ui.button("Load Level 2").clicked() {
-                for entity in level_items.iter() {
-                    com.entity(entity).despawn_recursive();
-                }
-                level2::setup_room(
-                    &mut com,
-                    &mut custom_materials,
-                    &mut emissive_materials,
-                    &asset_server,
-                );
-            }
-            if let Some(handle) = material_handles.iter_mut().next() {
-                let main_mat = if let Some(main_mat) = custom_materials.get_mut(&handle.clone()) {
-                    ui.collapsing("material properties", |ui| {
-                        main_mat.build_ui(ui, &mut com, &asset_server);
-                    });
-                    Some(main_mat.clone())
-                } else {
-                    None
-                };
-                if let Some(main_mat) = main_mat {
-                    for handle in material_handles.iter_mut() {
-                        if let Some(mat) = custom_materials.get_mut(&handle.clone()) {
-                            mat.material_properties = main_mat.material_properties
-                        }
-                    }
-                }
-            }
-            if let Some(mut controller) = controllers.iter_mut().next() {
-                controller.enabled = !ui.ctx().is_using_pointer();
-            }
+    let mut app = App::new();
+    // Hot reloading is left off for benchmarks so runs stay comparable
+    let mut plugins = DefaultPlugins
+        .set(AssetPlugin {
+            watch_for_changes: cfg!(feature = "hot-reload") && benchmark.is_none(),
+            ..default()
+        })
+        .set(WindowPlugin {
+            window: app_settings.window(),
+            add_primary_window: !headless,
+            exit_on_all_closed: !headless,
+            ..default()
         });
+    if headless {
+        // No window and no GPU, the app still updates and loads assets
+        app.insert_resource(WgpuSettings {
+            backends: None,
+            ..default()
+        });
+        plugins = plugins.disable::<WinitPlugin>();
+    }
+    app.add_plugins(plugins);
+    if headless {
+        app.add_plugin(ScheduleRunnerPlugin);
     }
-}
 
-fn player(mut com: Commands) {
-    // camera
-    com.spawn(Camera3dBundle {
-        transform: Transform::from_xyz(-2.0, 2.5, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
-        ..default()
-    })
-    .insert(CameraController::default().print_controls());
-}
+    // Inserted first so the plugin keeps them
+    #[cfg(feature = "planets")]
+    app.insert_resource(app_settings.spawn_config());
+    app.insert_resource(app_settings.walk.clone())
+        .insert_resource(app_settings)
+        .add_plugin(BakedGiPlugin {
+            level,
+            // The benchmark runs without the UI
+            ui: benchmark.is_none() && !args.no_ui,
+        });
 
-fn main() {
-    App::new()
-        .add_plugins(DefaultPlugins.set(AssetPlugin {
-            watch_for_changes: true,
-            ..default()
-        }))
-        .add_plugin(EguiPlugin)
-        .add_plugin(CameraControllerPlugin)
-        .add_plugin(MaterialPlugin::<CustomMaterial>::default())
-        .add_plugin(MaterialPlugin::<EmissiveMaterial>::default())
-        .add_system(menu_ui)
-        .add_startup_system(spawn_planets)
-        .add_startup_system(player)
-        .add_system(planitary_physics)
-        .add_system(set_texture_settings)
-        .run();
+    match benchmark {
+        // The benchmark drives the camera itself
+        Some(benchmark) => {
+            app.add_plugin(BenchmarkPlugin(benchmark));
+        }
+        None => {
+            #[cfg(feature = "fly-camera")]
+            app.add_plugin(FlyCameraPlugin);
+            app.add_system(track_window_size)
+                .add_system_to_stage(CoreStage::Last, save_settings_on_exit);
+        }
+    }
+    app.run();
 }
@@ -0,0 +1,137 @@
+use bevy::{asset::load_internal_asset, prelude::*, reflect::TypeUuid};
+use bevy_egui::egui;
+
+use crate::data_texture::{DataTexture, DataTexturePlugin};
+use crate::system_timings::timed;
+
+pub const OCCLUDERS_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x32dd9bf7949383f0);
+
+// Must match the array size in assets/shaders/occluders.wgsl
+pub const MAX_OCCLUDERS: usize = 32;
+
+// The settings texel and one per sphere
+const OCCLUDER_TEXELS: usize = MAX_OCCLUDERS + 1;
+
+// Spheres that darken the baked lighting, see assets/shaders/occluders.wgsl.
+// Texel 0 is (count, ambient_strength, shadow_strength, shadow_softness), the spheres follow
+// with xyz the center and w the radius.
+#[derive(Resource, Debug, Clone, PartialEq)]
+pub struct OccluderData {
+    pub texels: [Vec4; OCCLUDER_TEXELS],
+}
+
+impl Default for OccluderData {
+    fn default() -> Self {
+        let mut texels = [Vec4::ZERO; OCCLUDER_TEXELS];
+        texels[0] = Vec4::new(0.0, 0.0, 0.0, 1.0);
+        OccluderData { texels }
+    }
+}
+
+// Bound as CustomMaterial::occluders
+impl DataTexture for OccluderData {
+    const HANDLE: HandleUntyped =
+        HandleUntyped::weak_from_u64(Image::TYPE_UUID, 0x8d41f6c2e07a3b95);
+
+    fn texels(&self) -> &[Vec4] {
+        &self.texels
+    }
+}
+
+// A unit sphere mesh, the radius is taken from the scale
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct SphereOccluder;
+
+#[derive(Resource, Debug, Clone)]
+pub struct OccluderSettings {
+    pub enabled: bool,
+    // How much the occluders darken the lightmap around them
+    pub ambient_strength: f32,
+    // How much of the baked sun light is removed in their shadow
+    pub shadow_strength: f32,
+    // Higher is a sharper shadow edge
+    pub shadow_softness: f32,
+}
+
+impl Default for OccluderSettings {
+    fn default() -> Self {
+        OccluderSettings {
+            enabled: true,
+            ambient_strength: 1.0,
+            shadow_strength: 0.7,
+            shadow_softness: 4.0,
+        }
+    }
+}
+
+impl OccluderSettings {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.enabled, "enabled");
+        ui.add(egui::Slider::new(&mut self.ambient_strength, 0.0..=1.0).text("ambient_strength"));
+        ui.add(egui::Slider::new(&mut self.shadow_strength, 0.0..=1.0).text("shadow_strength"));
+        ui.add(
+            egui::Slider::new(&mut self.shadow_softness, 0.5..=32.0)
+                .logarithmic(true)
+                .text("shadow_softness"),
+        );
+    }
+}
+
+// Collects the occluders closest to the camera. The buffer is kept between frames
+pub fn sync_occluders(
+    settings: Res<OccluderSettings>,
+    mut data: ResMut<OccluderData>,
+    mut spheres: Local<Vec<Vec4>>,
+    cameras: Query<&GlobalTransform, With<Camera3d>>,
+    occluders: Query<&GlobalTransform, With<SphereOccluder>>,
+) {
+    spheres.clear();
+    if settings.enabled {
+        let view = cameras
+            .iter()
+            .next()
+            .map_or(Vec3::ZERO, |camera| camera.translation());
+        spheres.extend(occluders.iter().map(|transform| {
+            let (scale, _, translation) = transform.to_scale_rotation_translation();
+            translation.extend(scale.max_element())
+        }));
+        // Only the closest MAX_OCCLUDERS are needed, not their order
+        if spheres.len() > MAX_OCCLUDERS {
+            spheres.select_nth_unstable_by(MAX_OCCLUDERS, |a, b| {
+                let a = a.truncate().distance_squared(view);
+                let b = b.truncate().distance_squared(view);
+                a.total_cmp(&b)
+            });
+            spheres.truncate(MAX_OCCLUDERS);
+        }
+    }
+    let mut next = OccluderData::default();
+    next.texels[0] = Vec4::new(
+        spheres.len() as f32,
+        settings.ambient_strength,
+        settings.shadow_strength,
+        settings.shadow_softness,
+    );
+    next.texels[1..=spheres.len()].copy_from_slice(&spheres);
+    // Only marked as changed when it did, so the texture isn't written every frame
+    if *data != next {
+        *data = next;
+    }
+}
+
+pub struct OccludersPlugin;
+
+impl Plugin for OccludersPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            OCCLUDERS_SHADER_HANDLE,
+            "../assets/shaders/occluders.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugin(DataTexturePlugin::<OccluderData>::default())
+            .init_resource::<OccluderSettings>()
+            .add_system(timed(sync_occluders));
+    }
+}
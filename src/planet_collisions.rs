@@ -0,0 +1,180 @@
+use bevy::{prelude::*, utils::HashMap};
+
+use crate::planets::{planet_radius, spawn_planet, Planet, PlanetAssets, PlanetSettings};
+
+// A fragmenting planet breaks into this many equal pieces
+const FRAGMENT_DIRECTIONS: [Vec3; 4] = [
+    Vec3::new(0.57735, 0.57735, 0.57735),
+    Vec3::new(-0.57735, -0.57735, 0.57735),
+    Vec3::new(-0.57735, 0.57735, -0.57735),
+    Vec3::new(0.57735, -0.57735, -0.57735),
+];
+// Planets lighter than this won't fragment any further
+const MIN_FRAGMENT_MASS: f32 = 0.05;
+
+#[derive(Clone, Copy)]
+struct Body {
+    entity: Entity,
+    position: Vec3,
+    velocity: Vec3,
+    mass: f32,
+    alive: bool,
+    changed: bool,
+}
+
+// Kept between steps so the broad phase doesn't reallocate
+#[derive(Default)]
+pub struct CollisionScratch {
+    bodies: Vec<Body>,
+    cells: HashMap<IVec3, Vec<usize>>,
+    fragments: Vec<(Vec3, Vec3, f32)>,
+}
+
+fn apply_impulse(a: &mut Body, b: &mut Body, normal: Vec3, normal_speed: f32, restitution: f32) {
+    let impulse = -(1.0 + restitution) * normal_speed / (1.0 / a.mass + 1.0 / b.mass);
+    a.velocity -= normal * (impulse / a.mass);
+    b.velocity += normal * (impulse / b.mass);
+}
+
+fn collide(
+    settings: &PlanetSettings,
+    a: &mut Body,
+    b: &mut Body,
+    fragments: &mut Vec<(Vec3, Vec3, f32)>,
+) {
+    let offset = b.position - a.position;
+    let distance_squared = offset.length_squared();
+    let radius_sum = planet_radius(a.mass) + planet_radius(b.mass);
+    if distance_squared >= radius_sum * radius_sum || distance_squared == 0.0 {
+        return;
+    }
+    let distance = distance_squared.sqrt();
+    let normal = offset / distance;
+    // Relative speed along the normal, negative when approaching
+    let normal_speed = (b.velocity - a.velocity).dot(normal);
+    if normal_speed >= 0.0 {
+        return;
+    }
+    a.changed = true;
+    b.changed = true;
+
+    let reduced_mass = a.mass * b.mass / (a.mass + b.mass);
+    let impact_energy = 0.5 * reduced_mass * normal_speed * normal_speed;
+    let a_is_big = a.mass >= b.mass;
+    let fragment_mass = a.mass.min(b.mass) / FRAGMENT_DIRECTIONS.len() as f32;
+
+    if settings.fragmentation
+        && impact_energy > settings.fragmentation_energy
+        && fragment_mass >= MIN_FRAGMENT_MASS
+    {
+        // The smaller planet shatters, pieces fly off from where it was
+        apply_impulse(a, b, normal, normal_speed, settings.planet_restitution);
+        let small = if a_is_big { b } else { a };
+        small.alive = false;
+        let spread = -normal_speed * 0.5;
+        for direction in FRAGMENT_DIRECTIONS {
+            fragments.push((
+                small.position + direction * planet_radius(small.mass) * 0.75,
+                small.velocity + direction * spread,
+                fragment_mass,
+            ));
+        }
+    } else if settings.accretion {
+        // Merge into the bigger planet, keeping mass and momentum
+        let (big, small) = if a_is_big { (a, b) } else { (b, a) };
+        let mass = big.mass + small.mass;
+        big.velocity = (big.velocity * big.mass + small.velocity * small.mass) / mass;
+        big.position = (big.position * big.mass + small.position * small.mass) / mass;
+        big.mass = mass;
+        small.alive = false;
+    } else {
+        apply_impulse(a, b, normal, normal_speed, settings.planet_restitution);
+        // Push apart so they don't stay overlapping, lighter planets move further
+        let penetration = radius_sum - distance;
+        let inverse_mass_sum = 1.0 / a.mass + 1.0 / b.mass;
+        a.position -= normal * (penetration * (1.0 / a.mass) / inverse_mass_sum);
+        b.position += normal * (penetration * (1.0 / b.mass) / inverse_mass_sum);
+    }
+}
+
+pub fn planet_collisions(
+    mut commands: Commands,
+    settings: Res<PlanetSettings>,
+    assets: Res<PlanetAssets>,
+    mut scratch: Local<CollisionScratch>,
+    mut planet_query: Query<(Entity, &mut Planet, &mut Transform)>,
+) {
+    if !settings.collisions || settings.paused {
+        return;
+    }
+    let CollisionScratch {
+        bodies,
+        cells,
+        fragments,
+    } = &mut *scratch;
+    bodies.clear();
+    cells.clear();
+    fragments.clear();
+
+    let mut max_radius = 0.0f32;
+    for (entity, planet, transform) in planet_query.iter() {
+        max_radius = max_radius.max(planet.radius());
+        bodies.push(Body {
+            entity,
+            position: transform.translation,
+            velocity: planet.velocity,
+            mass: planet.mass,
+            alive: true,
+            changed: false,
+        });
+    }
+    if max_radius <= 0.0 {
+        return;
+    }
+
+    // Broad phase, uniform grid with cells as wide as the biggest planet
+    let cell_size = max_radius * 2.0;
+    let cell_of = |position: Vec3| (position / cell_size).floor().as_ivec3();
+    for (i, body) in bodies.iter().enumerate() {
+        cells.entry(cell_of(body.position)).or_default().push(i);
+    }
+
+    for i in 0..bodies.len() {
+        let cell = cell_of(bodies[i].position);
+        for x in -1..=1 {
+            for y in -1..=1 {
+                for z in -1..=1 {
+                    let others = match cells.get(&(cell + IVec3::new(x, y, z))) {
+                        Some(others) => others,
+                        None => continue,
+                    };
+                    for &j in others {
+                        if j <= i || !bodies[i].alive || !bodies[j].alive {
+                            continue;
+                        }
+                        let (head, tail) = bodies.split_at_mut(j);
+                        collide(&settings, &mut head[i], &mut tail[0], fragments);
+                    }
+                }
+            }
+        }
+    }
+
+    for body in bodies.iter().filter(|body| body.changed) {
+        if !body.alive {
+            commands.entity(body.entity).despawn_recursive();
+            continue;
+        }
+        if let Ok((_, mut planet, mut transform)) = planet_query.get_mut(body.entity) {
+            planet.velocity = body.velocity;
+            planet.mass = body.mass;
+            transform.translation = body.position;
+            transform.scale = Vec3::splat(planet_radius(body.mass));
+        }
+    }
+
+    for &(position, velocity, mass) in fragments.iter() {
+        let planet = Planet::new(velocity, mass);
+        spawn_planet(&mut commands, &assets, position, planet);
+    }
+}
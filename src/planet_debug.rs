@@ -0,0 +1,262 @@
+use std::collections::VecDeque;
+
+use bevy::{
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        mesh::PrimitiveTopology,
+        render_resource::{AsBindGroup, ShaderRef},
+        view::NoFrustumCulling,
+    },
+};
+use bevy_egui::egui;
+
+use crate::planets::Planet;
+use crate::system_timings::timed;
+
+const TRAIL_COLOR: [f32; 4] = [0.8, 0.8, 1.0, 1.0];
+const VELOCITY_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const FORCE_COLOR: [f32; 4] = [1.0, 0.2, 0.2, 1.0];
+
+// Unlit, alpha blended lines, multiplied by the vertex colors
+#[derive(AsBindGroup, Debug, Clone, TypeUuid)]
+#[uuid = "fcded5ea-f62b-4992-a51b-089633f6f2ad"]
+pub struct LineMaterial {
+    #[uniform(0)]
+    pub color: Color,
+}
+
+impl Material for LineMaterial {
+    fn fragment_shader() -> ShaderRef {
+        "shaders/line_material.wgsl".into()
+    }
+
+    fn alpha_mode(&self) -> AlphaMode {
+        AlphaMode::Blend
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct PlanetDebugSettings {
+    pub trails: bool,
+    // Positions kept per planet
+    pub trail_length: usize,
+    // A new trail point is recorded once the planet has moved this far
+    pub trail_spacing: f32,
+    pub velocity_arrows: bool,
+    // Net gravitational force, mass * acceleration
+    pub force_arrows: bool,
+    // Arrow length per unit of velocity or force
+    pub arrow_scale: f32,
+}
+
+impl Default for PlanetDebugSettings {
+    fn default() -> Self {
+        PlanetDebugSettings {
+            trails: false,
+            trail_length: 128,
+            trail_spacing: 0.05,
+            velocity_arrows: false,
+            force_arrows: false,
+            arrow_scale: 0.5,
+        }
+    }
+}
+
+impl PlanetDebugSettings {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.checkbox(&mut self.trails, "trails");
+        ui.add(egui::Slider::new(&mut self.trail_length, 2..=1024).text("trail_length"));
+        ui.add(
+            egui::Slider::new(&mut self.trail_spacing, 0.01..=1.0)
+                .logarithmic(true)
+                .text("trail_spacing"),
+        );
+        ui.checkbox(&mut self.velocity_arrows, "velocity arrows");
+        ui.checkbox(&mut self.force_arrows, "force arrows");
+        ui.add(
+            egui::Slider::new(&mut self.arrow_scale, 0.01..=10.0)
+                .logarithmic(true)
+                .text("arrow_scale"),
+        );
+    }
+}
+
+// Shows the trail or arrows for a single planet, even when they're globally off
+#[derive(Component, Debug, Clone, Copy, Default)]
+pub struct PlanetDebugView {
+    pub trail: bool,
+    pub arrows: bool,
+}
+
+// Ring buffer of past positions, oldest first
+#[derive(Component, Debug, Default)]
+pub struct PlanetTrail {
+    points: VecDeque<Vec3>,
+}
+
+// The single entity all trails and arrows are drawn with
+#[derive(Component)]
+struct PlanetLines;
+
+fn set_lines(mesh: &mut Mesh, positions: Vec<[f32; 3]>, colors: Vec<[f32; 4]>) {
+    mesh.insert_attribute(Mesh::ATTRIBUTE_POSITION, positions);
+    mesh.insert_attribute(Mesh::ATTRIBUTE_COLOR, colors);
+}
+
+fn spawn_planet_lines(
+    mut commands: Commands,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut materials: ResMut<Assets<LineMaterial>>,
+) {
+    // Starts out with a degenerate line, empty vertex buffers can't be drawn
+    let mut mesh = Mesh::new(PrimitiveTopology::LineList);
+    set_lines(&mut mesh, vec![[0.0; 3]; 2], vec![[0.0; 4]; 2]);
+    commands
+        .spawn(MaterialMeshBundle {
+            mesh: meshes.add(mesh),
+            material: materials.add(LineMaterial {
+                color: Color::WHITE,
+            }),
+            visibility: Visibility { is_visible: false },
+            ..Default::default()
+        })
+        // The mesh changes every frame, so its bounds would be stale
+        .insert((PlanetLines, NoFrustumCulling));
+}
+
+fn record_planet_trails(
+    mut commands: Commands,
+    settings: Res<PlanetDebugSettings>,
+    mut planet_query: Query<
+        (
+            Entity,
+            &Transform,
+            Option<&PlanetDebugView>,
+            Option<&mut PlanetTrail>,
+        ),
+        With<Planet>,
+    >,
+) {
+    for (entity, transform, view, trail) in planet_query.iter_mut() {
+        let enabled = settings.trails || view.map_or(false, |view| view.trail);
+        match trail {
+            Some(mut trail) if enabled => {
+                let position = transform.translation;
+                let moved = trail.points.back().map_or(true, |last| {
+                    last.distance(position) >= settings.trail_spacing
+                });
+                if moved {
+                    trail.points.push_back(position);
+                }
+                while trail.points.len() > settings.trail_length {
+                    trail.points.pop_front();
+                }
+            }
+            Some(_) => {
+                commands.entity(entity).remove::<PlanetTrail>();
+            }
+            None if enabled => {
+                commands.entity(entity).insert(PlanetTrail::default());
+            }
+            None => (),
+        }
+    }
+}
+
+fn push_arrow(
+    positions: &mut Vec<[f32; 3]>,
+    colors: &mut Vec<[f32; 4]>,
+    start: Vec3,
+    vector: Vec3,
+    color: [f32; 4],
+) {
+    let length = vector.length();
+    if length < 0.0001 {
+        return;
+    }
+    let end = start + vector;
+    let direction = vector / length;
+    let head = length.min(0.5) * 0.25;
+    let side = direction.any_orthonormal_vector() * head * 0.5;
+    for point in [
+        start,
+        end,
+        end,
+        end - direction * head + side,
+        end,
+        end - direction * head - side,
+    ] {
+        positions.push(point.to_array());
+        colors.push(color);
+    }
+}
+
+fn update_planet_lines(
+    settings: Res<PlanetDebugSettings>,
+    mut meshes: ResMut<Assets<Mesh>>,
+    mut lines: Query<(&Handle<Mesh>, &mut Visibility), With<PlanetLines>>,
+    planet_query: Query<(
+        &Planet,
+        &Transform,
+        Option<&PlanetDebugView>,
+        Option<&PlanetTrail>,
+    )>,
+) {
+    let (handle, mut visibility) = match lines.get_single_mut() {
+        Ok(lines) => lines,
+        Err(_) => return,
+    };
+    let mut positions = Vec::new();
+    let mut colors = Vec::new();
+    for (planet, transform, view, trail) in planet_query.iter() {
+        let position = transform.translation;
+        if let Some(trail) = trail {
+            // Fades out towards the oldest point, the last segment follows the planet
+            let count = trail.points.len() + 1;
+            let points = trail.points.iter().copied().chain(Some(position));
+            for (i, (a, b)) in points.clone().zip(points.skip(1)).enumerate() {
+                for (point, t) in [(a, i), (b, i + 1)] {
+                    let alpha = t as f32 / count as f32;
+                    positions.push(point.to_array());
+                    colors.push([TRAIL_COLOR[0], TRAIL_COLOR[1], TRAIL_COLOR[2], alpha]);
+                }
+            }
+        }
+        let arrows = view.map_or(false, |view| view.arrows);
+        if settings.velocity_arrows || arrows {
+            let vector = planet.velocity * settings.arrow_scale;
+            push_arrow(
+                &mut positions,
+                &mut colors,
+                position,
+                vector,
+                VELOCITY_COLOR,
+            );
+        }
+        if settings.force_arrows || arrows {
+            let vector = planet.acceleration * planet.mass * settings.arrow_scale;
+            push_arrow(&mut positions, &mut colors, position, vector, FORCE_COLOR);
+        }
+    }
+
+    visibility.is_visible = !positions.is_empty();
+    if positions.is_empty() {
+        return;
+    }
+    if let Some(mesh) = meshes.get_mut(handle) {
+        set_lines(mesh, positions, colors);
+    }
+}
+
+pub struct PlanetDebugPlugin;
+
+impl Plugin for PlanetDebugPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_plugin(MaterialPlugin::<LineMaterial>::default())
+            .init_resource::<PlanetDebugSettings>()
+            .add_startup_system(spawn_planet_lines)
+            .add_system(timed(record_planet_trails))
+            .add_system(timed(update_planet_lines).after(record_planet_trails));
+    }
+}
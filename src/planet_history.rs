@@ -0,0 +1,257 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use bevy_egui::egui;
+use serde::{Deserialize, Serialize};
+
+use crate::planets::{spawn_planet, Planet, PlanetAssets, PlanetSettings, PLANET_TIMESTEP};
+use crate::ron_file::{load_ron, save_ron};
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct PlanetState {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    // Kept so velocity Verlet continues exactly where it left off
+    pub acceleration: [f32; 3],
+    pub mass: f32,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct PlanetSnapshot {
+    // Simulated seconds since startup
+    pub time: f64,
+    pub planets: Vec<PlanetState>,
+}
+
+impl PlanetSnapshot {
+    pub fn capture<'a>(
+        time: f64,
+        planets: impl Iterator<Item = (&'a Planet, &'a Transform)>,
+    ) -> Self {
+        PlanetSnapshot {
+            time,
+            planets: planets
+                .map(|(planet, transform)| PlanetState {
+                    position: transform.translation.to_array(),
+                    velocity: planet.velocity.to_array(),
+                    acceleration: planet.acceleration.to_array(),
+                    mass: planet.mass,
+                })
+                .collect(),
+        }
+    }
+
+    // Replaces every planet with the ones in the snapshot
+    pub fn restore(
+        &self,
+        commands: &mut Commands,
+        assets: &PlanetAssets,
+        planets: impl Iterator<Item = Entity>,
+    ) {
+        for entity in planets {
+            commands.entity(entity).despawn_recursive();
+        }
+        for state in &self.planets {
+            let planet = Planet {
+                velocity: Vec3::from(state.velocity),
+                acceleration: Vec3::from(state.acceleration),
+                mass: state.mass,
+            };
+            spawn_planet(commands, assets, Vec3::from(state.position), planet);
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct PlanetHistory {
+    pub recording: bool,
+    // Snapshots are taken every this many physics steps
+    pub interval: u32,
+    // Oldest snapshots are dropped past this
+    pub capacity: usize,
+    // Index into snapshots while scrubbing the timeline, the simulation is paused meanwhile
+    pub cursor: Option<usize>,
+    // File used by Save and Load
+    pub path: String,
+    snapshots: VecDeque<PlanetSnapshot>,
+    // Snapshot index that's currently spawned
+    applied: Option<usize>,
+    steps: u64,
+}
+
+impl Default for PlanetHistory {
+    fn default() -> Self {
+        PlanetHistory {
+            recording: true,
+            interval: 4,
+            capacity: 900,
+            cursor: None,
+            path: String::from("planet_snapshot.ron"),
+            snapshots: VecDeque::new(),
+            applied: None,
+            steps: 0,
+        }
+    }
+}
+
+impl PlanetHistory {
+    pub fn time(&self) -> f64 {
+        self.steps as f64 * PLANET_TIMESTEP
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.cursor = None;
+        self.applied = None;
+    }
+
+    // Discards everything after the cursor and continues simulating from there
+    fn resume(&mut self, settings: &mut PlanetSettings) {
+        if let Some(cursor) = self.cursor.take() {
+            self.snapshots.truncate(cursor + 1);
+            if let Some(snapshot) = self.snapshots.back() {
+                self.steps = (snapshot.time / PLANET_TIMESTEP).round() as u64;
+            }
+        }
+        self.applied = None;
+        settings.paused = false;
+    }
+
+    pub fn build_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        settings: &mut PlanetSettings,
+        snapshot_events: &mut EventWriter<PlanetSnapshotEvent>,
+    ) {
+        ui.checkbox(&mut self.recording, "record");
+        ui.add(egui::Slider::new(&mut self.interval, 1..=60).text("interval"));
+        ui.add(egui::Slider::new(&mut self.capacity, 10..=10000).text("capacity"));
+        if !self.snapshots.is_empty() {
+            let last = self.snapshots.len() - 1;
+            let mut cursor = self.cursor.unwrap_or(last);
+            let time = self.snapshots[cursor.min(last)].time;
+            let response = ui.add(
+                egui::Slider::new(&mut cursor, 0..=last)
+                    .show_value(false)
+                    .text(format!("{:.2}s", time)),
+            );
+            if response.changed() {
+                self.cursor = Some(cursor);
+                settings.paused = true;
+            }
+        }
+        ui.horizontal(|ui| {
+            let label = if settings.paused { "Resume" } else { "Pause" };
+            if ui.button(label).clicked() {
+                if settings.paused {
+                    self.resume(settings);
+                } else {
+                    settings.paused = true;
+                }
+            }
+            if ui.button("Clear History").clicked() {
+                self.clear();
+            }
+        });
+        ui.horizontal(|ui| {
+            ui.text_edit_singleline(&mut self.path);
+            if ui.button("Save").clicked() {
+                snapshot_events.send(PlanetSnapshotEvent::Save);
+            }
+            if ui.button("Load").clicked() {
+                snapshot_events.send(PlanetSnapshotEvent::Load);
+            }
+        });
+    }
+}
+
+pub enum PlanetSnapshotEvent {
+    // Writes the current planets to PlanetHistory::path
+    Save,
+    // Replaces the planets with the ones saved at PlanetHistory::path
+    Load,
+}
+
+// Runs after each physics step
+pub fn record_planet_history(
+    settings: Res<PlanetSettings>,
+    mut history: ResMut<PlanetHistory>,
+    planet_query: Query<(&Planet, &Transform)>,
+) {
+    if settings.paused {
+        return;
+    }
+    history.steps += 1;
+    if !history.recording || history.steps % history.interval.max(1) as u64 != 0 {
+        return;
+    }
+    let snapshot = PlanetSnapshot::capture(history.time(), planet_query.iter());
+    history.snapshots.push_back(snapshot);
+    while history.snapshots.len() > history.capacity {
+        history.snapshots.pop_front();
+    }
+}
+
+pub fn scrub_planet_history(
+    mut commands: Commands,
+    assets: Res<PlanetAssets>,
+    mut history: ResMut<PlanetHistory>,
+    planet_query: Query<Entity, With<Planet>>,
+) {
+    if history.cursor == history.applied {
+        return;
+    }
+    history.applied = history.cursor;
+    if let Some(snapshot) = history
+        .cursor
+        .and_then(|cursor| history.snapshots.get(cursor))
+    {
+        snapshot.restore(&mut commands, &assets, planet_query.iter());
+    }
+}
+
+fn save_snapshot(path: &str, snapshot: &PlanetSnapshot) {
+    match save_ron(path, snapshot) {
+        Ok(()) => info!("Saved {} planets to {}", snapshot.planets.len(), path),
+        Err(e) => error!("Failed to save planets to {}: {}", path, e),
+    }
+}
+
+fn load_snapshot(path: &str) -> Option<PlanetSnapshot> {
+    match load_ron(path) {
+        Ok(snapshot) => Some(snapshot),
+        Err(e) => {
+            error!("Failed to load planets from {}: {}", path, e);
+            None
+        }
+    }
+}
+
+pub fn planet_snapshot_events(
+    mut commands: Commands,
+    mut events: EventReader<PlanetSnapshotEvent>,
+    assets: Res<PlanetAssets>,
+    mut history: ResMut<PlanetHistory>,
+    planet_query: Query<(Entity, &Planet, &Transform)>,
+) {
+    for event in events.iter() {
+        match event {
+            PlanetSnapshotEvent::Save => {
+                let planets = planet_query
+                    .iter()
+                    .map(|(_, planet, transform)| (planet, transform));
+                let snapshot = PlanetSnapshot::capture(history.time(), planets);
+                save_snapshot(&history.path, &snapshot);
+            }
+            PlanetSnapshotEvent::Load => {
+                if let Some(snapshot) = load_snapshot(&history.path) {
+                    // The recorded history doesn't lead up to the loaded planets
+                    history.clear();
+                    history.steps = (snapshot.time / PLANET_TIMESTEP).round() as u64;
+                    let planets = planet_query.iter().map(|(entity, ..)| entity);
+                    snapshot.restore(&mut commands, &assets, planets);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use bevy::{math::Ray, prelude::*};
+use bevy_egui::{egui, EguiContext};
+
+use crate::planet_debug::PlanetDebugView;
+use crate::planets::{planet_radius, spawn_planet, Planet, PlanetAssets};
+use crate::PlayerCamera;
+
+// The left button is taken by the camera controller
+const PICK_BUTTON: MouseButton = MouseButton::Right;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PickTool {
+    // Drag a planet around, it keeps its velocity when released
+    Grab,
+    // Click and drag to spawn a planet, the drag sets its velocity
+    Spawn,
+    Delete,
+}
+
+impl PickTool {
+    pub const ALL: [PickTool; 3] = [PickTool::Grab, PickTool::Spawn, PickTool::Delete];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PickTool::Grab => "Grab",
+            PickTool::Spawn => "Spawn",
+            PickTool::Delete => "Delete",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Drag {
+    // distance is along the pick ray, so the planet stays at the same depth
+    Planet { entity: Entity, distance: f32 },
+    Spawn { start: Vec3, distance: f32 },
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct PlanetPicking {
+    pub tool: PickTool,
+    pub spawn_mass: f32,
+    // Distance from the camera new planets are spawned at
+    pub spawn_distance: f32,
+    // Multiplies the velocity given by flinging or spawn dragging
+    pub fling_scale: f32,
+    // Planet shown in the inspector
+    pub selected: Option<Entity>,
+    drag: Option<Drag>,
+}
+
+impl Default for PlanetPicking {
+    fn default() -> Self {
+        PlanetPicking {
+            tool: PickTool::Grab,
+            spawn_mass: 1.0,
+            spawn_distance: 5.0,
+            fling_scale: 1.0,
+            selected: None,
+            drag: None,
+        }
+    }
+}
+
+impl PlanetPicking {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.label("Right click to use the tool");
+        egui::ComboBox::from_label("tool")
+            .selected_text(self.tool.name())
+            .show_ui(ui, |ui| {
+                for tool in PickTool::ALL {
+                    ui.selectable_value(&mut self.tool, tool, tool.name());
+                }
+            });
+        ui.add(
+            egui::Slider::new(&mut self.spawn_mass, 0.05..=20.0)
+                .logarithmic(true)
+                .text("spawn_mass"),
+        );
+        ui.add(egui::Slider::new(&mut self.spawn_distance, 0.5..=30.0).text("spawn_distance"));
+        ui.add(egui::Slider::new(&mut self.fling_scale, 0.0..=5.0).text("fling_scale"));
+    }
+
+    pub fn build_inspector_ui(
+        &mut self,
+        ui: &mut egui::Ui,
+        planet_query: &mut Query<(&mut Planet, &mut Transform, &mut PlanetDebugView)>,
+    ) {
+        let entity = match self.selected {
+            Some(entity) => entity,
+            None => {
+                ui.label("Grab a planet to inspect it");
+                return;
+            }
+        };
+        let (mut planet, mut transform, mut view) = match planet_query.get_mut(entity) {
+            Ok(selected) => selected,
+            Err(_) => {
+                // Despawned, merged or fragmented
+                self.selected = None;
+                return;
+            }
+        };
+        let p = transform.translation;
+        let v = planet.velocity;
+        ui.label(format!("position: {:.3} {:.3} {:.3}", p.x, p.y, p.z));
+        ui.label(format!(
+            "velocity: {:.3} {:.3} {:.3} (|v| {:.3})",
+            v.x,
+            v.y,
+            v.z,
+            v.length()
+        ));
+        let mass = ui.add(
+            egui::Slider::new(&mut planet.mass, 0.01..=20.0)
+                .logarithmic(true)
+                .text("mass"),
+        );
+        if mass.changed() {
+            transform.scale = Vec3::splat(planet_radius(planet.mass));
+        }
+        ui.checkbox(&mut view.trail, "trail");
+        ui.checkbox(&mut view.arrows, "arrows");
+        if ui.button("Deselect").clicked() {
+            self.selected = None;
+        }
+    }
+}
+
+// Distance along the ray to the first intersection with the sphere
+fn ray_sphere(ray: &Ray, center: Vec3, radius: f32) -> Option<f32> {
+    let offset = ray.origin - center;
+    let b = offset.dot(ray.direction);
+    let c = offset.length_squared() - radius * radius;
+    let h = b * b - c;
+    if h < 0.0 {
+        return None;
+    }
+    let h = h.sqrt();
+    // Use the far side when the ray starts inside the sphere
+    let t = if -b - h >= 0.0 { -b - h } else { -b + h };
+    (t >= 0.0).then_some(t)
+}
+
+fn pick_planet<'a>(
+    ray: &Ray,
+    planets: impl Iterator<Item = (Entity, &'a Planet, &'a Transform)>,
+) -> Option<Entity> {
+    planets
+        .filter_map(|(entity, planet, transform)| {
+            ray_sphere(ray, transform.translation, planet.radius()).map(|t| (entity, t))
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))
+        .map(|(entity, _)| entity)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn planet_picking(
+    mut commands: Commands,
+    mut picking: ResMut<PlanetPicking>,
+    assets: Res<PlanetAssets>,
+    windows: Res<Windows>,
+    buttons: Res<Input<MouseButton>>,
+    time: Res<Time>,
+    mut egui_context: ResMut<EguiContext>,
+    cameras: Query<(&Camera, &GlobalTransform), With<PlayerCamera>>,
+    mut planet_query: Query<(Entity, &mut Planet, &mut Transform)>,
+) {
+    let cursor = windows
+        .get_primary()
+        .and_then(|window| window.cursor_position());
+    let ray = match (cursor, cameras.get_single()) {
+        (Some(cursor), Ok((camera, camera_transform))) => {
+            camera.viewport_to_world(camera_transform, cursor)
+        }
+        _ => None,
+    };
+    let ray = match ray {
+        Some(ray) => ray,
+        None => return,
+    };
+
+    if buttons.just_pressed(PICK_BUTTON) && !egui_context.ctx_mut().wants_pointer_input() {
+        let picked = pick_planet(&ray, planet_query.iter());
+        match picking.tool {
+            PickTool::Grab => {
+                if let Some(entity) = picked {
+                    let (_, _, transform) = planet_query.get(entity).unwrap();
+                    let distance = (transform.translation - ray.origin).dot(ray.direction);
+                    picking.selected = Some(entity);
+                    picking.drag = Some(Drag::Planet { entity, distance });
+                }
+            }
+            PickTool::Spawn => {
+                let distance = picking.spawn_distance;
+                picking.drag = Some(Drag::Spawn {
+                    start: ray.origin + ray.direction * distance,
+                    distance,
+                });
+            }
+            PickTool::Delete => {
+                if let Some(entity) = picked {
+                    commands.entity(entity).despawn_recursive();
+                    if picking.selected == Some(entity) {
+                        picking.selected = None;
+                    }
+                }
+            }
+        }
+    }
+
+    let drag = match picking.drag {
+        Some(drag) => drag,
+        None => return,
+    };
+    let released = !buttons.pressed(PICK_BUTTON);
+    match drag {
+        Drag::Planet { entity, distance } => {
+            let (_, mut planet, mut transform) = match planet_query.get_mut(entity) {
+                Ok(planet) => planet,
+                Err(_) => {
+                    picking.drag = None;
+                    return;
+                }
+            };
+            let target = ray.origin + ray.direction * distance;
+            let dt = time.delta_seconds();
+            if dt > 0.0 {
+                planet.velocity = (target - transform.translation) / dt;
+            }
+            planet.acceleration = Vec3::ZERO;
+            transform.translation = target;
+            if released {
+                planet.velocity *= picking.fling_scale;
+            }
+        }
+        Drag::Spawn { start, distance } => {
+            if released {
+                let end = ray.origin + ray.direction * distance;
+                let velocity = (end - start) * picking.fling_scale;
+                let planet = Planet::new(velocity, picking.spawn_mass);
+                let entity = spawn_planet(&mut commands, &assets, start, planet);
+                picking.selected = Some(entity);
+            }
+        }
+    }
+    if released {
+        picking.drag = None;
+    }
+}
@@ -1,76 +1,601 @@
-use bevy::prelude::*;
-use rand::Rng;
+use bevy::{
+    ecs::{schedule::ShouldRun, system::SystemParam},
+    prelude::*,
+    time::FixedTimestep,
+};
+use bevy_egui::egui;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
+
+use crate::app_state::LevelLoaded;
+use crate::barnes_hut::Octree;
+use crate::fog_standard_material::FogStandardMaterial;
+use crate::level_collision::LevelCollider;
+use crate::occluders::SphereOccluder;
+use crate::planet_collisions::planet_collisions;
+use crate::planet_debug::{PlanetDebugSettings, PlanetDebugView};
+use crate::planet_history::{
+    planet_snapshot_events, record_planet_history, scrub_planet_history, PlanetHistory,
+    PlanetSnapshotEvent,
+};
+use crate::planet_picking::{planet_picking, PlanetPicking};
+use crate::system_timings::timed;
+
+// The simulation always advances by this much per step, regardless of frame rate
+pub const PLANET_TIMESTEP: f64 = 1.0 / 60.0;
+
+#[derive(Resource, Debug, Clone)]
+pub struct PlanetSettings {
+    // Barnes-Hut opening angle, 0.0 is an exact (and slow) direct sum
+    pub theta: f32,
+    // Integration steps per PLANET_TIMESTEP
+    pub substeps: u32,
+    pub diagnostics: bool,
+    // Physics and history recording are stopped, e.g. while scrubbing the timeline
+    pub paused: bool,
+    // Exactly one PLANET_TIMESTEP per frame instead of following the clock, for benchmarks
+    pub step_every_frame: bool,
+    // Fraction of the normal velocity kept when bouncing off the level
+    pub restitution: f32,
+    // Coulomb friction coefficient against the level
+    pub friction: f32,
+    pub collisions: bool,
+    // 1.0 is elastic, lower values lose energy on impact
+    pub planet_restitution: f32,
+    // Colliding planets merge instead of bouncing
+    pub accretion: bool,
+    pub fragmentation: bool,
+    // Impact energy above which the smaller planet breaks apart
+    pub fragmentation_energy: f32,
+}
+
+impl Default for PlanetSettings {
+    fn default() -> Self {
+        PlanetSettings {
+            theta: 0.5,
+            substeps: 4,
+            diagnostics: false,
+            paused: false,
+            step_every_frame: false,
+            restitution: 0.8,
+            friction: 0.2,
+            collisions: true,
+            planet_restitution: 0.5,
+            accretion: true,
+            fragmentation: true,
+            fragmentation_energy: 2.0,
+        }
+    }
+}
+
+impl PlanetSettings {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui, diagnostics: &PlanetDiagnostics) {
+        ui.add(egui::Slider::new(&mut self.theta, 0.0..=2.0).text("theta"));
+        ui.add(egui::Slider::new(&mut self.substeps, 1..=16).text("substeps"));
+        ui.add(egui::Slider::new(&mut self.restitution, 0.0..=1.0).text("restitution"));
+        ui.add(egui::Slider::new(&mut self.friction, 0.0..=1.0).text("friction"));
+        ui.checkbox(&mut self.collisions, "planet collisions");
+        if self.collisions {
+            ui.add(
+                egui::Slider::new(&mut self.planet_restitution, 0.0..=1.0)
+                    .text("planet_restitution"),
+            );
+            ui.checkbox(&mut self.accretion, "accretion");
+            ui.checkbox(&mut self.fragmentation, "fragmentation");
+            ui.add(
+                egui::Slider::new(&mut self.fragmentation_energy, 0.01..=100.0)
+                    .logarithmic(true)
+                    .text("fragmentation_energy"),
+            );
+        }
+        ui.checkbox(&mut self.diagnostics, "diagnostics");
+        if self.diagnostics {
+            diagnostics.build_ui(ui);
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone, Default)]
+pub struct PlanetDiagnostics {
+    pub kinetic_energy: f32,
+    pub potential_energy: f32,
+    pub momentum: Vec3,
+}
+
+impl PlanetDiagnostics {
+    pub fn build_ui(&self, ui: &mut egui::Ui) {
+        ui.label(format!("kinetic energy: {:.4}", self.kinetic_energy));
+        ui.label(format!("potential energy: {:.4}", self.potential_energy));
+        ui.label(format!(
+            "total energy: {:.4}",
+            self.kinetic_energy + self.potential_energy
+        ));
+        ui.label(format!(
+            "momentum: {:.4} {:.4} {:.4} (|p| {:.4})",
+            self.momentum.x,
+            self.momentum.y,
+            self.momentum.z,
+            self.momentum.length()
+        ));
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MassDistribution {
+    Uniform,
+    // Many small planets and a few big ones
+    LogUniform,
+}
+
+impl MassDistribution {
+    pub const ALL: [MassDistribution; 2] =
+        [MassDistribution::Uniform, MassDistribution::LogUniform];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            MassDistribution::Uniform => "Uniform",
+            MassDistribution::LogUniform => "Log Uniform",
+        }
+    }
+
+    fn sample(&self, rng: &mut ChaCha8Rng, min: f32, max: f32) -> f32 {
+        if min >= max {
+            return min;
+        }
+        match self {
+            MassDistribution::Uniform => rng.gen_range(min..max),
+            MassDistribution::LogUniform => rng.gen_range(min.ln()..max.ln()).exp(),
+        }
+    }
+}
+
+// What the planets initially orbit around
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Attractor {
+    // Planets start at rest
+    None,
+    Heaviest,
+    CenterOfMass,
+}
+
+impl Attractor {
+    pub const ALL: [Attractor; 3] = [
+        Attractor::None,
+        Attractor::Heaviest,
+        Attractor::CenterOfMass,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Attractor::None => "None",
+            Attractor::Heaviest => "Heaviest",
+            Attractor::CenterOfMass => "Center Of Mass",
+        }
+    }
+}
+
+#[derive(Resource, Debug, Clone)]
+pub struct PlanetSpawnConfig {
+    pub count: u32,
+    // The same seed always spawns the same planets, ChaCha8Rng is portable and stable across
+    // releases unlike StdRng
+    pub seed: u64,
+    pub volume_min: Vec3,
+    pub volume_max: Vec3,
+    pub mass_min: f32,
+    pub mass_max: f32,
+    pub mass_distribution: MassDistribution,
+    pub attractor: Attractor,
+    // 1.0 is a circular orbit around the attractor, ignoring the other planets
+    pub orbit_speed: f32,
+}
+
+impl Default for PlanetSpawnConfig {
+    fn default() -> Self {
+        PlanetSpawnConfig {
+            count: 30,
+            seed: 0,
+            volume_min: Vec3::new(-6.0, 4.0, -6.0),
+            volume_max: Vec3::new(6.0, 7.0, 6.0),
+            mass_min: 0.05,
+            mass_max: 5.0,
+            mass_distribution: MassDistribution::Uniform,
+            attractor: Attractor::None,
+            orbit_speed: 1.0,
+        }
+    }
+}
+
+fn vec3_ui(ui: &mut egui::Ui, label: &str, v: &mut Vec3) {
+    ui.horizontal(|ui| {
+        ui.add(egui::DragValue::new(&mut v.x).speed(0.1));
+        ui.add(egui::DragValue::new(&mut v.y).speed(0.1));
+        ui.add(egui::DragValue::new(&mut v.z).speed(0.1));
+        ui.label(label);
+    });
+}
+
+// Speed of a circular orbit at distance r from mass, using the same softening as the gravity
+fn orbit_speed(mass: f32, r: f32) -> f32 {
+    if r >= mass {
+        (mass / r).sqrt()
+    } else {
+        (r / mass).sqrt()
+    }
+}
+
+impl PlanetSpawnConfig {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.count, 0..=500).text("count"));
+        ui.horizontal(|ui| {
+            ui.add(egui::DragValue::new(&mut self.seed));
+            ui.label("seed");
+            if ui.button("Random Seed").clicked() {
+                self.seed = rand::random();
+            }
+        });
+        vec3_ui(ui, "volume min", &mut self.volume_min);
+        vec3_ui(ui, "volume max", &mut self.volume_max);
+        ui.add(
+            egui::Slider::new(&mut self.mass_min, 0.01..=10.0)
+                .logarithmic(true)
+                .text("mass_min"),
+        );
+        ui.add(
+            egui::Slider::new(&mut self.mass_max, 0.01..=10.0)
+                .logarithmic(true)
+                .text("mass_max"),
+        );
+        egui::ComboBox::from_label("mass distribution")
+            .selected_text(self.mass_distribution.name())
+            .show_ui(ui, |ui| {
+                for distribution in MassDistribution::ALL {
+                    ui.selectable_value(
+                        &mut self.mass_distribution,
+                        distribution,
+                        distribution.name(),
+                    );
+                }
+            });
+        egui::ComboBox::from_label("attractor")
+            .selected_text(self.attractor.name())
+            .show_ui(ui, |ui| {
+                for attractor in Attractor::ALL {
+                    ui.selectable_value(&mut self.attractor, attractor, attractor.name());
+                }
+            });
+        if self.attractor != Attractor::None {
+            ui.add(egui::Slider::new(&mut self.orbit_speed, 0.0..=2.0).text("orbit_speed"));
+        }
+    }
+
+    // Returns (position, velocity, mass) for every planet
+    pub fn generate(&self) -> Vec<(Vec3, Vec3, f32)> {
+        let mut rng = ChaCha8Rng::seed_from_u64(self.seed);
+        let min = self.volume_min.min(self.volume_max);
+        let max = self.volume_min.max(self.volume_max);
+        let mass_min = self.mass_min.min(self.mass_max);
+        let mass_max = self.mass_min.max(self.mass_max);
+
+        let mut bodies: Vec<(Vec3, Vec3, f32)> = (0..self.count)
+            .map(|_| {
+                let t = Vec3::new(rng.gen(), rng.gen(), rng.gen());
+                let position = min + (max - min) * t;
+                let mass = self.mass_distribution.sample(&mut rng, mass_min, mass_max);
+                (position, Vec3::ZERO, mass)
+            })
+            .collect();
+
+        let attractor = match self.attractor {
+            Attractor::None => return bodies,
+            Attractor::Heaviest => bodies
+                .iter()
+                .enumerate()
+                .max_by(|(_, a), (_, b)| a.2.total_cmp(&b.2))
+                .map(|(i, &(position, _, mass))| (Some(i), position, mass)),
+            Attractor::CenterOfMass => {
+                let mass: f32 = bodies.iter().map(|body| body.2).sum();
+                let weighted: Vec3 = bodies.iter().map(|body| body.0 * body.2).sum();
+                (mass > 0.0).then(|| (None, weighted / mass, mass))
+            }
+        };
+        if let Some((index, center, mass)) = attractor {
+            for (i, body) in bodies.iter_mut().enumerate() {
+                if Some(i) == index {
+                    continue;
+                }
+                let offset = body.0 - center;
+                // Orbit in the horizontal plane, counterclockwise seen from above
+                let direction = Vec3::Y.cross(offset).normalize_or_zero();
+                let r = offset.length();
+                body.1 = direction * orbit_speed(mass, r) * self.orbit_speed;
+            }
+        }
+        bodies
+    }
+
+    pub fn spawn(&self, commands: &mut Commands, assets: &PlanetAssets) {
+        for (position, velocity, mass) in self.generate() {
+            spawn_planet(commands, assets, position, Planet::new(velocity, mass));
+        }
+    }
+}
+
+// Despawns every planet and spawns a new set from the PlanetSpawnConfig
+pub struct RespawnPlanets;
+
+// Everything the Settings window needs for the planets
+#[derive(SystemParam)]
+pub struct PlanetUi<'w, 's> {
+    settings: ResMut<'w, PlanetSettings>,
+    diagnostics: Res<'w, PlanetDiagnostics>,
+    spawn_config: ResMut<'w, PlanetSpawnConfig>,
+    debug: ResMut<'w, PlanetDebugSettings>,
+    history: ResMut<'w, PlanetHistory>,
+    picking: ResMut<'w, PlanetPicking>,
+    planet_query: Query<
+        'w,
+        's,
+        (
+            &'static mut Planet,
+            &'static mut Transform,
+            &'static mut PlanetDebugView,
+        ),
+    >,
+    respawn: EventWriter<'w, 's, RespawnPlanets>,
+    snapshot_events: EventWriter<'w, 's, PlanetSnapshotEvent>,
+}
+
+impl PlanetUi<'_, '_> {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        self.settings.build_ui(ui, &self.diagnostics);
+        ui.collapsing("spawning", |ui| {
+            self.spawn_config.build_ui(ui);
+        });
+        ui.collapsing("debug view", |ui| {
+            self.debug.build_ui(ui);
+        });
+        ui.collapsing("history", |ui| {
+            self.history
+                .build_ui(ui, &mut self.settings, &mut self.snapshot_events);
+        });
+        ui.collapsing("picking", |ui| {
+            self.picking.build_ui(ui);
+        });
+        ui.collapsing("inspector", |ui| {
+            self.picking.build_inspector_ui(ui, &mut self.planet_query);
+        });
+        if ui.button("Respawn").clicked() {
+            self.respawn.send(RespawnPlanets);
+        }
+    }
+}
 
 #[derive(Component, Debug)]
 pub struct Planet {
-    velocity: Vec3,
-    mass: f32,
+    pub velocity: Vec3,
+    // From the previous step, for velocity Verlet
+    pub acceleration: Vec3,
+    pub mass: f32,
+}
+
+pub fn planet_radius(mass: f32) -> f32 {
+    mass * 0.1
+}
+
+impl Planet {
+    pub fn new(velocity: Vec3, mass: f32) -> Self {
+        Planet {
+            velocity,
+            acceleration: Vec3::ZERO,
+            mass,
+        }
+    }
+
+    pub fn radius(&self) -> f32 {
+        planet_radius(self.mass)
+    }
+}
+
+// Shared by all planets, the mesh is a unit sphere scaled by the planet radius
+#[derive(Resource)]
+pub struct PlanetAssets {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<FogStandardMaterial>,
+}
+
+impl FromWorld for PlanetAssets {
+    fn from_world(world: &mut World) -> Self {
+        let mesh = world
+            .resource_mut::<Assets<Mesh>>()
+            .add(Mesh::from(shape::UVSphere::default()));
+        let material =
+            world
+                .resource_mut::<Assets<FogStandardMaterial>>()
+                .add(FogStandardMaterial {
+                    base_color: Color::rgb(0.1, 0.1, 0.1),
+                    ..Default::default()
+                });
+        PlanetAssets { mesh, material }
+    }
+}
+
+pub fn spawn_planet(
+    commands: &mut Commands,
+    assets: &PlanetAssets,
+    position: Vec3,
+    planet: Planet,
+) -> Entity {
+    commands
+        .spawn(MaterialMeshBundle {
+            mesh: assets.mesh.clone(),
+            material: assets.material.clone(),
+            transform: Transform::from_translation(position)
+                .with_scale(Vec3::splat(planet.radius())),
+            ..Default::default()
+        })
+        .insert(planet)
+        .insert(PlanetDebugView::default())
+        .insert(SphereOccluder)
+        .id()
 }
 
 pub fn spawn_planets(
     mut commands: Commands,
-    mut meshes: ResMut<Assets<Mesh>>,
-    mut materials: ResMut<Assets<StandardMaterial>>,
+    assets: Res<PlanetAssets>,
+    config: Res<PlanetSpawnConfig>,
 ) {
-    let mut rng = rand::thread_rng();
+    config.spawn(&mut commands, &assets);
+}
 
-    let n = 6.0;
+pub fn respawn_planets(
+    mut commands: Commands,
+    mut events: EventReader<RespawnPlanets>,
+    assets: Res<PlanetAssets>,
+    config: Res<PlanetSpawnConfig>,
+    mut history: ResMut<PlanetHistory>,
+    planet_query: Query<Entity, With<Planet>>,
+) {
+    if events.iter().count() == 0 {
+        return;
+    }
+    for entity in planet_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    // Scrubbing back would bring the old planets back
+    history.clear();
+    config.spawn(&mut commands, &assets);
+}
 
-    for _ in 0..30 {
-        let x = rng.gen_range(-n..n);
-        let y = rng.gen_range(4.0..7.0);
-        let z = rng.gen_range(-n..n);
+// Each level starts with a fresh set of planets
+fn respawn_on_level_loaded(
+    mut loaded: EventReader<LevelLoaded>,
+    mut respawn: EventWriter<RespawnPlanets>,
+) {
+    if loaded.iter().last().is_some() {
+        respawn.send(RespawnPlanets);
+    }
+}
 
-        let mass = rng.gen_range(0.05..5.0);
+// Velocity Verlet, run from a fixed timestep so trajectories don't depend on frame rate
+pub fn planitary_physics(
+    settings: Res<PlanetSettings>,
+    collider: Res<LevelCollider>,
+    mut octree: Local<Octree>,
+    mut planet_query: Query<(&mut Planet, &mut Transform)>,
+) {
+    if settings.paused {
+        return;
+    }
+    let substeps = settings.substeps.max(1);
+    let dt = PLANET_TIMESTEP as f32 / substeps as f32;
+    let theta = settings.theta;
+    let restitution = settings.restitution;
+    let friction = settings.friction;
+    let bvh = &collider.bvh;
+    for _ in 0..substeps {
+        planet_query.par_for_each_mut(64, |(planet, mut transform)| {
+            transform.translation += planet.velocity * dt + 0.5 * planet.acceleration * dt * dt;
+        });
+        octree.build(|| {
+            planet_query
+                .iter()
+                .map(|(planet, transform)| (transform.translation, planet.mass))
+        });
+        let tree = &*octree;
+        planet_query.par_for_each_mut(64, |(mut planet, mut transform)| {
+            let acceleration = tree.acceleration(transform.translation, theta);
+            planet.velocity += 0.5 * (planet.acceleration + acceleration) * dt;
+            planet.acceleration = acceleration;
 
-        commands
-            .spawn(PbrBundle {
-                mesh: meshes.add(Mesh::from(shape::UVSphere {
-                    radius: mass * 0.1,
-                    ..Default::default()
-                })),
-                material: materials.add(StandardMaterial {
-                    base_color: Color::rgb(0.1, 0.1, 0.1),
-                    ..Default::default()
-                }),
-                transform: Transform::from_xyz(x, y, z),
-                ..Default::default()
-            })
-            .insert(Planet {
-                velocity: Vec3::new(0.0, 0.0, 0.0),
-                mass,
+            let radius = planet.radius();
+            let mut push = Vec3::ZERO;
+            bvh.sphere_contacts(transform.translation, radius, |contact| {
+                // Neighbouring triangles of the same surface shouldn't push twice
+                let along = push.dot(contact.normal);
+                if along < contact.depth {
+                    push += contact.normal * (contact.depth - along);
+                }
+                let normal_speed = planet.velocity.dot(contact.normal);
+                if normal_speed >= 0.0 {
+                    return;
+                }
+                let tangent_velocity = planet.velocity - contact.normal * normal_speed;
+                // Friction impulse is limited by the normal impulse
+                let tangent_speed = tangent_velocity.length();
+                let normal_impulse = -normal_speed * (1.0 + restitution);
+                let tangent_scale = if tangent_speed > 0.0 {
+                    (1.0 - friction * normal_impulse / tangent_speed).max(0.0)
+                } else {
+                    0.0
+                };
+                planet.velocity =
+                    tangent_velocity * tangent_scale - contact.normal * normal_speed * restitution;
             });
+            transform.translation += push;
+        });
     }
 }
 
-pub fn planitary_physics(time: Res<Time>, mut planet_query: Query<(&mut Planet, &mut Transform)>) {
-    let mut pos_mass = Vec::new(); //todo, don't allocate
+pub fn planet_diagnostics(
+    settings: Res<PlanetSettings>,
+    mut diagnostics: ResMut<PlanetDiagnostics>,
+    mut octree: Local<Octree>,
+    planet_query: Query<(&Planet, &Transform)>,
+) {
+    if !settings.diagnostics {
+        return;
+    }
+    octree.build(|| {
+        planet_query
+            .iter()
+            .map(|(planet, transform)| (transform.translation, planet.mass))
+    });
+    let mut result = PlanetDiagnostics::default();
     for (planet, transform) in planet_query.iter() {
-        pos_mass.push((transform.translation, planet.mass));
-    }
-    for (i, (mut planet, mut transform)) in planet_query.iter_mut().enumerate() {
-        for (j, (transform_a, mass_a)) in pos_mass.iter().enumerate() {
-            if i != j {
-                let difference = transform.translation - *transform_a;
-                let d = difference.powf(2.0);
-                let distance_squared = d.x + d.y + d.z;
-
-                planet.velocity -= (difference).normalize()
-                    * ((1.0 / mass_a.powf(2.0).max(distance_squared)) * mass_a)
-                    * time.delta_seconds();
-            }
-        }
-        transform.translation += planet.velocity * time.delta_seconds();
-        let r = planet.mass * 0.1;
-        //bounce off walls-ish
-        let hit = transform.translation.x < -25.0 + r
-            || transform.translation.x > 20.0 - r
-            || transform.translation.y < 0.1 + r
-            || transform.translation.y > 16.0 - r
-            || transform.translation.z < -14.0 + r
-            || transform.translation.z > 8.0 - r;
-        if hit {
-            planet.velocity *= -1.0;
-        }
+        result.kinetic_energy += 0.5 * planet.mass * planet.velocity.length_squared();
+        // Each pair is seen from both sides
+        result.potential_energy +=
+            0.5 * planet.mass * octree.potential(transform.translation, settings.theta);
+        result.momentum += planet.mass * planet.velocity;
+    }
+    *diagnostics = result;
+}
+
+// The FixedTimestep, or one step per frame with PlanetSettings::step_every_frame
+fn planet_timestep(In(fixed): In<ShouldRun>, settings: Res<PlanetSettings>) -> ShouldRun {
+    if settings.step_every_frame {
+        ShouldRun::Yes
+    } else {
+        fixed
+    }
+}
+
+pub struct PlanetsPlugin;
+
+impl Plugin for PlanetsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PlanetSettings>()
+            .init_resource::<PlanetDiagnostics>()
+            .init_resource::<PlanetAssets>()
+            .init_resource::<PlanetSpawnConfig>()
+            .init_resource::<PlanetHistory>()
+            .init_resource::<PlanetPicking>()
+            .add_event::<RespawnPlanets>()
+            .add_event::<PlanetSnapshotEvent>()
+            .add_startup_system(spawn_planets)
+            .add_system(respawn_on_level_loaded.before(respawn_planets))
+            .add_system(respawn_planets)
+            .add_system(planet_snapshot_events)
+            .add_system(scrub_planet_history)
+            .add_system(timed(planet_picking))
+            .add_system_set(
+                SystemSet::new()
+                    .with_run_criteria(FixedTimestep::step(PLANET_TIMESTEP).pipe(planet_timestep))
+                    .with_system(timed(planitary_physics))
+                    .with_system(timed(planet_collisions).after(planitary_physics))
+                    .with_system(timed(record_planet_history).after(planet_collisions)),
+            )
+            .add_system(timed(planet_diagnostics));
     }
 }
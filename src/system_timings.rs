@@ -0,0 +1,120 @@
+use std::{
+    borrow::Cow,
+    collections::BTreeMap,
+    sync::{Arc, Mutex},
+    time::Instant,
+};
+
+use bevy::{
+    ecs::{
+        archetype::ArchetypeComponentId, component::ComponentId, query::Access,
+        schedule::SystemLabelId,
+    },
+    prelude::*,
+};
+
+// Run times in seconds of every timed system, keyed by system name. Only collected while this
+// resource exists, so normal runs don't pay for it
+#[derive(Resource, Debug, Clone, Default)]
+pub struct SystemTimings(Arc<Mutex<BTreeMap<Cow<'static, str>, Vec<f32>>>>);
+
+impl SystemTimings {
+    fn record(&self, name: Cow<'static, str>, seconds: f32) {
+        self.0
+            .lock()
+            .unwrap()
+            .entry(name)
+            .or_default()
+            .push(seconds);
+    }
+
+    pub fn clear(&self) {
+        self.0.lock().unwrap().clear();
+    }
+
+    pub fn take(&self) -> BTreeMap<Cow<'static, str>, Vec<f32>> {
+        std::mem::take(&mut *self.0.lock().unwrap())
+    }
+}
+
+// Wraps a system to measure how long each run takes on the CPU. This works without the trace
+// feature and without a renderer, unlike the tracing spans bevy puts around systems
+pub struct Timed<S> {
+    system: S,
+    timings: Option<SystemTimings>,
+}
+
+pub fn timed<Params>(
+    system: impl IntoSystem<(), (), Params>,
+) -> Timed<impl System<In = (), Out = ()>> {
+    Timed {
+        system: IntoSystem::into_system(system),
+        timings: None,
+    }
+}
+
+impl<S: System<In = (), Out = ()>> System for Timed<S> {
+    type In = ();
+    type Out = ();
+
+    fn name(&self) -> Cow<'static, str> {
+        self.system.name()
+    }
+
+    fn component_access(&self) -> &Access<ComponentId> {
+        self.system.component_access()
+    }
+
+    fn archetype_component_access(&self) -> &Access<ArchetypeComponentId> {
+        self.system.archetype_component_access()
+    }
+
+    fn is_send(&self) -> bool {
+        self.system.is_send()
+    }
+
+    fn is_exclusive(&self) -> bool {
+        self.system.is_exclusive()
+    }
+
+    unsafe fn run_unsafe(&mut self, input: (), world: &World) {
+        match &self.timings {
+            Some(timings) => {
+                let start = Instant::now();
+                self.system.run_unsafe(input, world);
+                timings.record(self.system.name(), start.elapsed().as_secs_f32());
+            }
+            None => self.system.run_unsafe(input, world),
+        }
+    }
+
+    fn apply_buffers(&mut self, world: &mut World) {
+        self.system.apply_buffers(world);
+    }
+
+    fn initialize(&mut self, world: &mut World) {
+        self.timings = world.get_resource::<SystemTimings>().cloned();
+        self.system.initialize(world);
+    }
+
+    fn update_archetype_component_access(&mut self, world: &World) {
+        self.system.update_archetype_component_access(world);
+    }
+
+    fn check_change_tick(&mut self, change_tick: u32) {
+        self.system.check_change_tick(change_tick);
+    }
+
+    // Keeps the function's own label, so .after(system) still works on the wrapped system
+    fn default_labels(&self) -> Vec<SystemLabelId> {
+        self.system.default_labels()
+    }
+
+    fn get_last_change_tick(&self) -> u32 {
+        self.system.get_last_change_tick()
+    }
+
+    fn set_last_change_tick(&mut self, last_change_tick: u32) {
+        self.system.set_last_change_tick(last_change_tick);
+    }
+}
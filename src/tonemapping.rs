@@ -0,0 +1,359 @@
+use bevy::{
+    asset::load_internal_asset,
+    core_pipeline::{core_3d, fullscreen_vertex_shader::fullscreen_shader_vertex_state},
+    ecs::query::QueryItem,
+    prelude::*,
+    reflect::TypeUuid,
+    render::{
+        extract_component::{
+            ComponentUniforms, DynamicUniformIndex, ExtractComponent, ExtractComponentPlugin,
+            UniformComponentPlugin,
+        },
+        render_graph::{Node, NodeRunError, RenderGraph, RenderGraphContext, SlotInfo, SlotType},
+        render_resource::{
+            BindGroupDescriptor, BindGroupEntry, BindGroupLayout, BindGroupLayoutDescriptor,
+            BindGroupLayoutEntry, BindingResource, BindingType, BufferBindingType,
+            CachedRenderPipelineId, ColorTargetState, ColorWrites, FragmentState, MultisampleState,
+            Operations, PipelineCache, PrimitiveState, RenderPassColorAttachment,
+            RenderPassDescriptor, RenderPipelineDescriptor, ShaderStages, ShaderType,
+            TextureFormat, TextureSampleType, TextureViewDimension,
+        },
+        renderer::{RenderContext, RenderDevice},
+        texture::BevyDefault,
+        view::ViewTarget,
+        RenderApp,
+    },
+};
+use bevy_egui::egui;
+
+pub const TONEMAPPING_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0xf91819db4ae54040);
+pub const EXPOSURE_SHADER_HANDLE: HandleUntyped =
+    HandleUntyped::weak_from_u64(Shader::TYPE_UUID, 0x3a7c5e19d04b82f6);
+
+// Between the main pass and bevy's tonemapping node in the core_3d graph
+pub const EXPOSURE_NODE: &str = "exposure";
+
+// EV100 that bevy bakes into directional lights (f/4, 1/250s, ISO 100).
+// Exposing at this value leaves colors unchanged.
+pub const DEFAULT_EV100: f32 = 11.965784;
+pub const MIN_EV100: f32 = 6.0;
+pub const MAX_EV100: f32 = 18.0;
+
+// Keep in sync with the TONEMAPPER_ constants in assets/shaders/tonemapping.wgsl
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tonemapper {
+    None = 0,
+    Reinhard = 1,
+    Aces = 2,
+    AgX = 3,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 4] = [
+        Tonemapper::None,
+        Tonemapper::Reinhard,
+        Tonemapper::Aces,
+        Tonemapper::AgX,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tonemapper::None => "None",
+            Tonemapper::Reinhard => "Reinhard",
+            Tonemapper::Aces => "ACES",
+            Tonemapper::AgX => "AgX",
+        }
+    }
+}
+
+// Per view, extracted from the CameraExposure
+#[derive(Component, ShaderType, Debug, Clone, Copy, PartialEq)]
+pub struct ExposureUniform {
+    pub exposure: f32,
+    pub tonemapper: u32,
+}
+
+impl ExtractComponent for ExposureUniform {
+    type Query = &'static CameraExposure;
+    type Filter = ();
+
+    fn extract_component(exposure: QueryItem<Self::Query>) -> Self {
+        exposure.uniform()
+    }
+}
+
+// Put on an hdr camera alongside Tonemapping::Disabled. The materials output linear color,
+// which ExposureNode exposes and tone maps.
+#[derive(Component, Debug, Clone)]
+pub struct CameraExposure {
+    pub ev100: f32,
+    pub tonemapper: Tonemapper,
+    // ev100 follows the luminance histogram of the view, see auto_exposure.rs
+    pub auto: bool,
+    // In stops, positive is brighter
+    pub compensation: f32,
+    // How fast auto exposure adapts, higher is faster
+    pub adaptation_speed: f32,
+    // Shows the linear color as is, for debug views
+    pub raw: bool,
+}
+
+impl Default for CameraExposure {
+    fn default() -> Self {
+        CameraExposure {
+            ev100: DEFAULT_EV100,
+            tonemapper: Tonemapper::Reinhard,
+            auto: false,
+            compensation: 0.0,
+            adaptation_speed: 2.0,
+            raw: false,
+        }
+    }
+}
+
+impl CameraExposure {
+    pub fn uniform(&self) -> ExposureUniform {
+        if self.raw {
+            return ExposureUniform {
+                exposure: 1.0,
+                tonemapper: Tonemapper::None as u32,
+            };
+        }
+        ExposureUniform {
+            exposure: 2.0f32.powf(DEFAULT_EV100 - self.ev100),
+            tonemapper: self.tonemapper as u32,
+        }
+    }
+
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.add(egui::Slider::new(&mut self.ev100, MIN_EV100..=MAX_EV100).text("ev100"));
+        if ui.button("Reset Exposure").clicked() {
+            self.ev100 = DEFAULT_EV100;
+        }
+        ui.checkbox(&mut self.auto, "auto exposure");
+        if self.auto {
+            ui.add(egui::Slider::new(&mut self.compensation, -4.0..=4.0).text("compensation"));
+            ui.add(
+                egui::Slider::new(&mut self.adaptation_speed, 0.1..=10.0)
+                    .logarithmic(true)
+                    .text("adaptation_speed"),
+            );
+        }
+        egui::ComboBox::from_label("tonemapper")
+            .selected_text(self.tonemapper.name())
+            .show_ui(ui, |ui| {
+                for tonemapper in Tonemapper::ALL {
+                    ui.selectable_value(&mut self.tonemapper, tonemapper, tonemapper.name());
+                }
+            });
+    }
+}
+
+// Exposes and tone maps the linear view texture of cameras with a CameraExposure
+struct ExposureNode {
+    query: QueryState<(
+        &'static ViewTarget,
+        &'static DynamicUniformIndex<ExposureUniform>,
+    )>,
+}
+
+impl ExposureNode {
+    const IN_VIEW: &'static str = "view";
+
+    fn new(world: &mut World) -> Self {
+        ExposureNode {
+            query: QueryState::new(world),
+        }
+    }
+}
+
+impl Node for ExposureNode {
+    fn input(&self) -> Vec<SlotInfo> {
+        vec![SlotInfo::new(Self::IN_VIEW, SlotType::Entity)]
+    }
+
+    fn update(&mut self, world: &mut World) {
+        self.query.update_archetypes(world);
+    }
+
+    fn run(
+        &self,
+        graph: &mut RenderGraphContext,
+        render_context: &mut RenderContext,
+        world: &World,
+    ) -> Result<(), NodeRunError> {
+        let view_entity = graph.get_input_entity(Self::IN_VIEW)?;
+        let (target, uniform_index) = match self.query.get_manual(world, view_entity) {
+            Ok(result) => result,
+            Err(_) => return Ok(()),
+        };
+        let exposure = world.resource::<ExposurePipeline>();
+        let pipeline_id = if target.is_hdr() {
+            exposure.hdr_pipeline
+        } else {
+            exposure.pipeline
+        };
+        let pipeline = match world
+            .resource::<PipelineCache>()
+            .get_render_pipeline(pipeline_id)
+        {
+            Some(pipeline) => pipeline,
+            None => return Ok(()),
+        };
+        let uniforms = match world
+            .resource::<ComponentUniforms<ExposureUniform>>()
+            .binding()
+        {
+            Some(uniforms) => uniforms,
+            None => return Ok(()),
+        };
+
+        let post_process = target.post_process_write();
+        let bind_group = render_context
+            .render_device
+            .create_bind_group(&BindGroupDescriptor {
+                label: Some("exposure_bind_group"),
+                layout: &exposure.layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(post_process.source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: uniforms,
+                    },
+                ],
+            });
+        let mut pass = render_context
+            .command_encoder
+            .begin_render_pass(&RenderPassDescriptor {
+                label: Some("exposure_pass"),
+                color_attachments: &[Some(RenderPassColorAttachment {
+                    view: post_process.destination,
+                    resolve_target: None,
+                    ops: Operations::default(),
+                })],
+                depth_stencil_attachment: None,
+            });
+        pass.set_pipeline(pipeline);
+        pass.set_bind_group(0, &bind_group, &[uniform_index.index()]);
+        pass.draw(0..3, 0..1);
+        Ok(())
+    }
+}
+
+#[derive(Resource)]
+struct ExposurePipeline {
+    layout: BindGroupLayout,
+    pipeline: CachedRenderPipelineId,
+    hdr_pipeline: CachedRenderPipelineId,
+}
+
+impl FromWorld for ExposurePipeline {
+    fn from_world(world: &mut World) -> Self {
+        let render_device = world.resource::<RenderDevice>();
+        let layout = render_device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("exposure_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: Some(ExposureUniform::min_size()),
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let mut pipeline_cache = world.resource_mut::<PipelineCache>();
+        let mut queue = |format| {
+            pipeline_cache.queue_render_pipeline(RenderPipelineDescriptor {
+                label: Some("exposure_pipeline".into()),
+                layout: Some(vec![layout.clone()]),
+                vertex: fullscreen_shader_vertex_state(),
+                fragment: Some(FragmentState {
+                    shader: EXPOSURE_SHADER_HANDLE.typed(),
+                    shader_defs: vec![],
+                    entry_point: "fragment".into(),
+                    targets: vec![Some(ColorTargetState {
+                        format,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    })],
+                }),
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+            })
+        };
+        let pipeline = queue(TextureFormat::bevy_default());
+        let hdr_pipeline = queue(ViewTarget::TEXTURE_FORMAT_HDR);
+        ExposurePipeline {
+            layout,
+            pipeline,
+            hdr_pipeline,
+        }
+    }
+}
+
+pub struct TonemappingPlugin;
+
+impl Plugin for TonemappingPlugin {
+    fn build(&self, app: &mut App) {
+        load_internal_asset!(
+            app,
+            TONEMAPPING_SHADER_HANDLE,
+            "../assets/shaders/tonemapping.wgsl",
+            Shader::from_wgsl
+        );
+        load_internal_asset!(
+            app,
+            EXPOSURE_SHADER_HANDLE,
+            "../assets/shaders/exposure.wgsl",
+            Shader::from_wgsl
+        );
+        app.add_plugin(ExtractComponentPlugin::<ExposureUniform>::default())
+            .add_plugin(UniformComponentPlugin::<ExposureUniform>::default());
+
+        let render_app = match app.get_sub_app_mut(RenderApp) {
+            Ok(render_app) => render_app,
+            Err(_) => return,
+        };
+        render_app.init_resource::<ExposurePipeline>();
+
+        let node = ExposureNode::new(&mut render_app.world);
+        let mut render_graph = render_app.world.resource_mut::<RenderGraph>();
+        let graph = render_graph
+            .get_sub_graph_mut(core_3d::graph::NAME)
+            .unwrap();
+        graph.add_node(EXPOSURE_NODE, node);
+        graph
+            .add_slot_edge(
+                graph.input_node().unwrap().id,
+                core_3d::graph::input::VIEW_ENTITY,
+                EXPOSURE_NODE,
+                ExposureNode::IN_VIEW,
+            )
+            .unwrap();
+        graph
+            .add_node_edge(core_3d::graph::node::MAIN_PASS, EXPOSURE_NODE)
+            .unwrap();
+        graph
+            .add_node_edge(EXPOSURE_NODE, core_3d::graph::node::TONEMAPPING)
+            .unwrap();
+    }
+}
@@ -0,0 +1,295 @@
+use bevy::{input::mouse::MouseMotion, prelude::*, window::CursorGrabMode};
+use bevy_egui::{egui, EguiContext};
+use serde::{Deserialize, Serialize};
+
+use crate::camera_path::CameraPathPlayer;
+use crate::level_collision::{LevelCollider, TriangleBvh};
+
+// Longest step the character is moved in one go, so it can't tunnel through thin walls
+const MAX_TIMESTEP: f32 = 1.0 / 120.0;
+const RESOLVE_ITERATIONS: usize = 4;
+// The step height is walked back down in this many increments, each smaller than the radius
+const DOWN_STEPS: usize = 4;
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CameraMode {
+    // Free flying CameraController, needs the fly-camera feature
+    Fly,
+    // Capsule character with gravity that collides with the level
+    Walk,
+}
+
+#[derive(Resource, Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
+pub struct WalkSettings {
+    pub mode: CameraMode,
+    // Capsule size, from the feet up
+    pub height: f32,
+    pub radius: f32,
+    pub eye_height: f32,
+    pub walk_speed: f32,
+    pub run_speed: f32,
+    pub jump_speed: f32,
+    pub gravity: f32,
+    // Ledges up to this high are climbed without jumping
+    pub step_height: f32,
+    // Steepest walkable slope in degrees, anything steeper is a wall
+    pub max_slope: f32,
+    // How quickly the velocity follows the keys while in the air
+    pub air_control: f32,
+    pub sensitivity: f32,
+}
+
+impl Default for WalkSettings {
+    fn default() -> Self {
+        WalkSettings {
+            // Without the fly camera nothing else moves it
+            mode: if cfg!(feature = "fly-camera") {
+                CameraMode::Fly
+            } else {
+                CameraMode::Walk
+            },
+            height: 1.8,
+            radius: 0.3,
+            eye_height: 1.65,
+            walk_speed: 3.0,
+            run_speed: 6.0,
+            jump_speed: 4.5,
+            gravity: 9.81,
+            step_height: 0.35,
+            max_slope: 45.0,
+            air_control: 2.0,
+            sensitivity: 0.2,
+        }
+    }
+}
+
+impl WalkSettings {
+    pub fn build_ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.mode, CameraMode::Fly, "Fly");
+            ui.selectable_value(&mut self.mode, CameraMode::Walk, "Walk");
+        });
+        if self.mode != CameraMode::Walk {
+            return;
+        }
+        ui.label("WASD to move, Shift to run, Space to jump");
+        ui.add(egui::Slider::new(&mut self.height, 0.5..=3.0).text("height"));
+        ui.add(egui::Slider::new(&mut self.radius, 0.1..=1.0).text("radius"));
+        ui.add(egui::Slider::new(&mut self.eye_height, 0.2..=3.0).text("eye_height"));
+        ui.add(egui::Slider::new(&mut self.walk_speed, 0.5..=10.0).text("walk_speed"));
+        ui.add(egui::Slider::new(&mut self.run_speed, 0.5..=20.0).text("run_speed"));
+        ui.add(egui::Slider::new(&mut self.jump_speed, 0.0..=10.0).text("jump_speed"));
+        ui.add(egui::Slider::new(&mut self.gravity, 0.0..=30.0).text("gravity"));
+        ui.add(egui::Slider::new(&mut self.step_height, 0.0..=1.0).text("step_height"));
+        ui.add(egui::Slider::new(&mut self.max_slope, 0.0..=89.0).text("max_slope"));
+        ui.add(egui::Slider::new(&mut self.air_control, 0.0..=10.0).text("air_control"));
+        ui.add(egui::Slider::new(&mut self.sensitivity, 0.01..=1.0).text("sensitivity"));
+    }
+}
+
+// Per camera walk state, only updated while in CameraMode::Walk
+#[derive(Component, Debug, Default)]
+pub struct Walker {
+    pub velocity: Vec3,
+    pub grounded: bool,
+    yaw: f32,
+    pitch: f32,
+    // Was walking last frame, the look angles are taken from the transform when this starts
+    active: bool,
+}
+
+impl Walker {
+    // Takes the look angles from the transform again and drops any velocity, for teleports
+    pub fn reset(&mut self) {
+        self.active = false;
+    }
+}
+
+// Three spheres stacked from the feet up
+struct Capsule {
+    height: f32,
+    radius: f32,
+}
+
+impl Capsule {
+    fn spheres(&self, feet: Vec3) -> [Vec3; 3] {
+        let bottom = feet + Vec3::Y * self.radius;
+        let top = feet + Vec3::Y * (self.height - self.radius).max(self.radius);
+        [bottom, (bottom + top) * 0.5, top]
+    }
+
+    // Pushes the capsule out of the level, f is called with every contact normal
+    fn resolve(&self, bvh: &TriangleBvh, feet: &mut Vec3, mut f: impl FnMut(Vec3)) {
+        for _ in 0..RESOLVE_ITERATIONS {
+            let mut push = Vec3::ZERO;
+            for center in self.spheres(*feet) {
+                bvh.sphere_contacts(center + push, self.radius, |contact| {
+                    // Neighbouring triangles of the same surface shouldn't push twice
+                    let along = push.dot(contact.normal);
+                    if along < contact.depth {
+                        push += contact.normal * (contact.depth - along);
+                    }
+                    f(contact.normal);
+                });
+            }
+            if push == Vec3::ZERO {
+                return;
+            }
+            *feet += push;
+        }
+    }
+}
+
+struct Motion {
+    velocity: Vec3,
+    grounded: bool,
+    // Cosine of the steepest walkable slope
+    walkable: f32,
+}
+
+impl Motion {
+    fn contact(&mut self, normal: Vec3) {
+        if normal.y >= self.walkable {
+            self.grounded = true;
+        }
+        // Stop moving into whatever was hit
+        self.velocity -= normal * self.velocity.dot(normal).min(0.0);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn walk_camera(
+    time: Res<Time>,
+    settings: Res<WalkSettings>,
+    path: Res<CameraPathPlayer>,
+    collider: Res<LevelCollider>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    mut mouse_motion: EventReader<MouseMotion>,
+    windows: Res<Windows>,
+    mut egui_context: ResMut<EguiContext>,
+    mut walkers: Query<(&mut Walker, &mut Transform)>,
+) {
+    let look: Vec2 = mouse_motion.iter().map(|motion| motion.delta).sum();
+    let (mut walker, mut transform) = match walkers.get_single_mut() {
+        Ok(walker) => walker,
+        Err(_) => return,
+    };
+    // A playing path drives the camera, the look angles are taken from it again afterwards
+    if settings.mode != CameraMode::Walk || path.playing {
+        walker.active = false;
+        return;
+    }
+    if !walker.active {
+        let (yaw, pitch, _) = transform.rotation.to_euler(EulerRot::YXZ);
+        walker.yaw = yaw;
+        walker.pitch = pitch;
+        walker.velocity = Vec3::ZERO;
+        walker.grounded = false;
+        walker.active = true;
+    }
+
+    // There's no egui context without a window
+    let (wants_pointer, wants_keyboard) = match windows.get_primary() {
+        Some(_) => {
+            let ctx = egui_context.ctx_mut();
+            (ctx.wants_pointer_input(), ctx.wants_keyboard_input())
+        }
+        None => (false, false),
+    };
+    // Same as the fly camera, look while the left button is held or the cursor is locked
+    let cursor_locked = windows.get_primary().map_or(false, |window| {
+        window.cursor_grab_mode() == CursorGrabMode::Locked
+    });
+    if cursor_locked || (mouse_buttons.pressed(MouseButton::Left) && !wants_pointer) {
+        let sensitivity = settings.sensitivity.to_radians();
+        walker.yaw -= look.x * sensitivity;
+        walker.pitch = (walker.pitch - look.y * sensitivity).clamp(-1.54, 1.54);
+    }
+    transform.rotation = Quat::from_euler(EulerRot::YXZ, walker.yaw, walker.pitch, 0.0);
+
+    let mut wish = Vec3::ZERO;
+    let mut jump = false;
+    if !wants_keyboard {
+        let axis = |positive: KeyCode, negative: KeyCode| {
+            keys.pressed(positive) as i32 as f32 - keys.pressed(negative) as i32 as f32
+        };
+        let direction = Vec3::new(
+            axis(KeyCode::D, KeyCode::A),
+            0.0,
+            -axis(KeyCode::W, KeyCode::S),
+        );
+        let speed = if keys.pressed(KeyCode::LShift) {
+            settings.run_speed
+        } else {
+            settings.walk_speed
+        };
+        wish = Quat::from_rotation_y(walker.yaw) * direction.normalize_or_zero() * speed;
+        jump = keys.just_pressed(KeyCode::Space);
+    }
+
+    let capsule = Capsule {
+        height: settings.height,
+        radius: settings.radius,
+    };
+    let bvh = &collider.bvh;
+    // Without a level there's nothing to stand on, so don't fall forever
+    let gravity = if bvh.is_empty() {
+        0.0
+    } else {
+        settings.gravity
+    };
+    let step_height = settings
+        .step_height
+        .min(capsule.radius * 0.9 * DOWN_STEPS as f32);
+
+    let dt = time.delta_seconds().min(0.1);
+    let steps = (dt / MAX_TIMESTEP).ceil().max(1.0) as u32;
+    let dt = dt / steps as f32;
+    let mut feet = transform.translation - Vec3::Y * settings.eye_height;
+    let mut motion = Motion {
+        velocity: walker.velocity,
+        grounded: walker.grounded,
+        walkable: settings.max_slope.to_radians().cos(),
+    };
+    for _ in 0..steps {
+        if motion.grounded {
+            motion.velocity.x = wish.x;
+            motion.velocity.z = wish.z;
+            motion.velocity.y = 0.0;
+            if jump {
+                motion.velocity.y = settings.jump_speed;
+                jump = false;
+            }
+        } else {
+            let t = (settings.air_control * dt).min(1.0);
+            motion.velocity.x += (wish.x - motion.velocity.x) * t;
+            motion.velocity.z += (wish.z - motion.velocity.z) * t;
+            motion.velocity.y -= gravity * dt;
+        }
+
+        let was_grounded = motion.grounded;
+        motion.grounded = false;
+        if was_grounded && motion.velocity.y <= 0.0 {
+            // Up, across and back down, so steps are climbed and slopes followed
+            feet.y += step_height;
+            capsule.resolve(bvh, &mut feet, |normal| motion.contact(normal));
+            feet += Vec3::new(motion.velocity.x, 0.0, motion.velocity.z) * dt;
+            capsule.resolve(bvh, &mut feet, |normal| motion.contact(normal));
+            for _ in 0..DOWN_STEPS * 2 {
+                if motion.grounded {
+                    break;
+                }
+                feet.y -= step_height / DOWN_STEPS as f32;
+                capsule.resolve(bvh, &mut feet, |normal| motion.contact(normal));
+            }
+        } else {
+            feet += motion.velocity * dt;
+            capsule.resolve(bvh, &mut feet, |normal| motion.contact(normal));
+        }
+    }
+    walker.velocity = motion.velocity;
+    walker.grounded = motion.grounded;
+    transform.translation = feet + Vec3::Y * settings.eye_height;
+}